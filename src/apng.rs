@@ -0,0 +1,565 @@
+//! Animated png chunks
+//!
+//! An APNG declares its frame count in an `acTL` chunk before the image data. Each frame starts
+//! with an `fcTL` chunk giving its region, delay and operations, followed by its image data: the
+//! IDAT chunks for a frame whose `fcTL` precedes them, `fdAT` chunks otherwise. `fcTL` and `fdAT`
+//! chunks share one sequence number counter starting at 0, which [`validate`] checks.
+
+use std::str::FromStr;
+use std::time::Duration;
+use std::{error, fmt, result};
+
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_type::ChunkType;
+
+/// Size of the acTL chunk data
+const ACTL_LEN: usize = 8;
+/// Size of the fcTL chunk data
+const FCTL_LEN: usize = 26;
+/// Size of the sequence number starting fcTL and fdAT chunk data
+const SEQUENCE_LEN: usize = 4;
+
+/// Animation chunk errors
+#[derive(Debug)]
+pub enum ApngError {
+  /// The chunk is not of the expected type
+  UnexpectedChunk(&'static str, ChunkType),
+  /// The chunk data has an incorrect number of bytes
+  InvalidLength(ChunkType, usize),
+  InvalidDisposeOp(u8),
+  InvalidBlendOp(u8),
+  /// acTL declares no frame
+  NoFrames,
+  /// A frame has a zero size or lies outside the image
+  InvalidRegion,
+  /// The frame of the default image does not cover the whole image from (0, 0)
+  InvalidDefaultRegion,
+  /// The sequence number is not the one following the previous fcTL or fdAT chunk
+  OutOfSequence { expected: u32, found: u32 },
+  /// acTL declares a different number of frames than there are fcTL chunks
+  FrameCount { declared: u32, actual: u32 },
+  /// An fdAT chunk does not follow an fcTL chunk
+  MissingFrameControl,
+  /// fcTL or fdAT chunks without an acTL chunk
+  NotAnimated,
+}
+
+impl error::Error for ApngError {}
+
+impl fmt::Display for ApngError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ApngError::UnexpectedChunk(expected, chunk_type) => {
+        write!(f, "Expected an {} chunk but found {}", expected, chunk_type)
+      }
+      ApngError::InvalidLength(chunk_type, actual) => {
+        write!(f, "Invalid {} data length {}", chunk_type, actual)
+      }
+      ApngError::InvalidDisposeOp(op) => write!(f, "Invalid dispose operation {}", op),
+      ApngError::InvalidBlendOp(op) => write!(f, "Invalid blend operation {}", op),
+      ApngError::NoFrames => write!(f, "The animation has no frames"),
+      ApngError::InvalidRegion => write!(f, "The frame lies outside the image"),
+      ApngError::InvalidDefaultRegion => {
+        write!(f, "The frame of the default image must cover the whole image from (0, 0)")
+      }
+      ApngError::OutOfSequence { expected, found } => {
+        write!(f, "Expected sequence number {} but found {}", expected, found)
+      }
+      ApngError::FrameCount { declared, actual } => {
+        write!(f, "acTL declares {} frames but there are {}", declared, actual)
+      }
+      ApngError::MissingFrameControl => write!(f, "fdAT chunk without a preceding fcTL chunk"),
+      ApngError::NotAnimated => write!(f, "Animation chunk without an acTL chunk"),
+    }
+  }
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+  u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn be_u16(data: &[u8]) -> u16 {
+  u16::from_be_bytes([data[0], data[1]])
+}
+
+/// Checks the chunk type and length of an acTL or fcTL chunk
+fn fixed_data<'a>(
+  chunk_type: &ChunkType,
+  data: &'a [u8],
+  name: &'static str,
+  len: usize,
+) -> result::Result<&'a [u8], ApngError> {
+  if chunk_type.to_string() != name {
+    return Err(ApngError::UnexpectedChunk(name, *chunk_type));
+  }
+  match data.len() == len {
+    true => Ok(data),
+    false => Err(ApngError::InvalidLength(*chunk_type, data.len())),
+  }
+}
+
+/// The sequence number of an fcTL or fdAT chunk, `None` for other chunks
+pub fn sequence_number(chunk_type: &ChunkType, data: &[u8]) -> Option<u32> {
+  match &chunk_type.bytes() {
+    b"fcTL" | b"fdAT" if data.len() >= SEQUENCE_LEN => Some(be_u32(data)),
+    _ => None,
+  }
+}
+
+/// The content of the acTL chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationControl {
+  pub num_frames: u32,
+  /// How many times the animation plays, 0 for forever
+  pub num_plays: u32,
+}
+
+impl AnimationControl {
+  /// Serializes the animation control as an acTL chunk
+  pub fn to_chunk(&self) -> Chunk {
+    let mut chunk_data = self.num_frames.to_be_bytes().to_vec();
+    chunk_data.extend(self.num_plays.to_be_bytes());
+    Chunk::new(ChunkType::from_str("acTL").unwrap(), chunk_data)
+  }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+  type Error = ApngError;
+  fn try_from(chunk: &Chunk) -> result::Result<AnimationControl, ApngError> {
+    AnimationControl::try_from((chunk.chunk_type(), chunk.data()))
+  }
+}
+
+impl TryFrom<(&ChunkType, &[u8])> for AnimationControl {
+  type Error = ApngError;
+  fn try_from((chunk_type, data): (&ChunkType, &[u8])) -> result::Result<AnimationControl, ApngError> {
+    let data = fixed_data(chunk_type, data, "acTL", ACTL_LEN)?;
+    let control = AnimationControl {
+      num_frames: be_u32(data),
+      num_plays: be_u32(&data[4..]),
+    };
+    match control.num_frames {
+      0 => Err(ApngError::NoFrames),
+      _ => Ok(control),
+    }
+  }
+}
+
+impl fmt::Display for AnimationControl {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.num_plays {
+      0 => write!(f, "{} frames, looping forever", self.num_frames),
+      plays => write!(f, "{} frames, played {} times", self.num_frames, plays),
+    }
+  }
+}
+
+/// How the frame area is cleared before rendering the next frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeOp {
+  /// Left as it is
+  None = 0,
+  /// Cleared to fully transparent black
+  Background = 1,
+  /// Reverted to its content before the frame
+  Previous = 2,
+}
+
+impl TryFrom<u8> for DisposeOp {
+  type Error = ApngError;
+  fn try_from(value: u8) -> result::Result<DisposeOp, ApngError> {
+    match value {
+      0 => Ok(DisposeOp::None),
+      1 => Ok(DisposeOp::Background),
+      2 => Ok(DisposeOp::Previous),
+      _ => Err(ApngError::InvalidDisposeOp(value)),
+    }
+  }
+}
+
+impl fmt::Display for DisposeOp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      DisposeOp::None => "none",
+      DisposeOp::Background => "background",
+      DisposeOp::Previous => "previous",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// How the frame is drawn over the output buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendOp {
+  /// The frame replaces the pixels of its area
+  Source = 0,
+  /// The frame is alpha composited over the pixels of its area
+  Over = 1,
+}
+
+impl TryFrom<u8> for BlendOp {
+  type Error = ApngError;
+  fn try_from(value: u8) -> result::Result<BlendOp, ApngError> {
+    match value {
+      0 => Ok(BlendOp::Source),
+      1 => Ok(BlendOp::Over),
+      _ => Err(ApngError::InvalidBlendOp(value)),
+    }
+  }
+}
+
+impl fmt::Display for BlendOp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      BlendOp::Source => "source",
+      BlendOp::Over => "over",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// The content of an fcTL chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+  pub sequence_number: u32,
+  pub width: u32,
+  pub height: u32,
+  pub x_offset: u32,
+  pub y_offset: u32,
+  /// Numerator of the delay in seconds
+  pub delay_num: u16,
+  /// Denominator of the delay in seconds, 0 meaning 100
+  pub delay_den: u16,
+  pub dispose_op: DisposeOp,
+  pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+  /// How long the frame is shown
+  pub fn delay(&self) -> Duration {
+    let den = match self.delay_den {
+      0 => 100,
+      den => den,
+    };
+    Duration::from_secs_f64(f64::from(self.delay_num) / f64::from(den))
+  }
+
+  /// Whether the frame has a size and fits in an image of the given dimensions
+  pub fn fits(&self, width: u32, height: u32) -> bool {
+    let fits = |offset: u32, size: u32, bound: u32| size > 0 && offset.checked_add(size).is_some_and(|end| end <= bound);
+    fits(self.x_offset, self.width, width) && fits(self.y_offset, self.height, height)
+  }
+
+  /// Serializes the frame control as an fcTL chunk
+  pub fn to_chunk(&self) -> Chunk {
+    let mut chunk_data = Vec::with_capacity(FCTL_LEN);
+    for value in [self.sequence_number, self.width, self.height, self.x_offset, self.y_offset] {
+      chunk_data.extend(value.to_be_bytes());
+    }
+    chunk_data.extend(self.delay_num.to_be_bytes());
+    chunk_data.extend(self.delay_den.to_be_bytes());
+    chunk_data.extend([self.dispose_op as u8, self.blend_op as u8]);
+    Chunk::new(ChunkType::from_str("fcTL").unwrap(), chunk_data)
+  }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+  type Error = ApngError;
+  fn try_from(chunk: &Chunk) -> result::Result<FrameControl, ApngError> {
+    FrameControl::try_from((chunk.chunk_type(), chunk.data()))
+  }
+}
+
+impl TryFrom<(&ChunkType, &[u8])> for FrameControl {
+  type Error = ApngError;
+  fn try_from((chunk_type, data): (&ChunkType, &[u8])) -> result::Result<FrameControl, ApngError> {
+    let data = fixed_data(chunk_type, data, "fcTL", FCTL_LEN)?;
+    Ok(FrameControl {
+      sequence_number: be_u32(data),
+      width: be_u32(&data[4..]),
+      height: be_u32(&data[8..]),
+      x_offset: be_u32(&data[12..]),
+      y_offset: be_u32(&data[16..]),
+      delay_num: be_u16(&data[20..]),
+      delay_den: be_u16(&data[22..]),
+      dispose_op: DisposeOp::try_from(data[24])?,
+      blend_op: BlendOp::try_from(data[25])?,
+    })
+  }
+}
+
+impl fmt::Display for FrameControl {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}x{} at ({}, {}), {} ms, dispose {}, blend {}",
+      self.width,
+      self.height,
+      self.x_offset,
+      self.y_offset,
+      self.delay().as_millis(),
+      self.dispose_op,
+      self.blend_op
+    )
+  }
+}
+
+/// A frame of an animation
+#[derive(Debug)]
+pub struct Frame<'a> {
+  /// Index of the fcTL chunk of the frame in the chunk list
+  pub index: usize,
+  pub control: FrameControl,
+  /// Whether the frame is the default image, stored in IDAT chunks
+  pub is_default_image: bool,
+  /// The compressed image data of each IDAT or fdAT chunk, without sequence numbers
+  pub data: Vec<&'a [u8]>,
+}
+
+impl Frame<'_> {
+  /// The compressed image data of the frame in one piece
+  pub fn image_data(&self) -> Vec<u8> {
+    self.data.concat()
+  }
+}
+
+/// Iterator over the frames of an animation, created by [`frames`]
+pub struct Frames<'a> {
+  chunks: &'a [Chunk],
+  index: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+  type Item = result::Result<Frame<'a>, ApngError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let is = |chunk: &Chunk, name: &[u8; 4]| chunk.chunk_type().bytes() == *name;
+    let start = self.index + self.chunks[self.index..].iter().position(|chunk| is(chunk, b"fcTL"))?;
+    let end = self.chunks[start + 1..]
+      .iter()
+      .position(|chunk| is(chunk, b"fcTL") || is(chunk, b"IEND"))
+      .map_or(self.chunks.len(), |end| start + 1 + end);
+    self.index = end;
+    let control = match FrameControl::try_from(&self.chunks[start]) {
+      Ok(control) => control,
+      Err(error) => return Some(Err(error)),
+    };
+    let mut frame = Frame {
+      index: start,
+      control,
+      is_default_image: false,
+      data: Vec::new(),
+    };
+    for chunk in &self.chunks[start + 1..end] {
+      if is(chunk, b"IDAT") {
+        frame.is_default_image = true;
+        frame.data.push(chunk.data());
+      } else if is(chunk, b"fdAT") {
+        match chunk.data().get(SEQUENCE_LEN..) {
+          Some(data) => frame.data.push(data),
+          None => return Some(Err(ApngError::InvalidLength(*chunk.chunk_type(), chunk.data().len()))),
+        }
+      }
+    }
+    Some(Ok(frame))
+  }
+}
+
+/// Iterates over the frames described by the fcTL chunks, in order
+pub fn frames(chunks: &[Chunk]) -> Frames<'_> {
+  Frames { chunks, index: 0 }
+}
+
+/// Checks the animation chunks: their sequence numbers, the frame count and frame regions
+///
+/// Returns each problem with the index of the chunk it was found at, `None` for the whole png.
+/// A png without animation chunks has none.
+pub fn validate(chunks: &[ChunkRef<'_>], width: u32, height: u32) -> Vec<(Option<usize>, ApngError)> {
+  let mut problems = Vec::new();
+  let is = |chunk: &ChunkRef<'_>, name: &[u8; 4]| chunk.chunk_type().bytes() == *name;
+  let animation = chunks.iter().find(|chunk| is(chunk, b"acTL"));
+  let control = match animation.map(|chunk| AnimationControl::try_from((chunk.chunk_type(), chunk.data()))) {
+    Some(Ok(control)) => Some(control),
+    Some(Err(error)) => {
+      let index = chunks.iter().position(|chunk| is(chunk, b"acTL"));
+      problems.push((index, error));
+      None
+    }
+    None => None,
+  };
+  let covers_image = |frame: &FrameControl| {
+    (frame.x_offset, frame.y_offset, frame.width, frame.height) == (0, 0, width, height)
+  };
+  let mut expected = 0;
+  let mut frame_count = 0;
+  let mut last_frame_control = None;
+  let mut last_image_data = None;
+  for (index, chunk) in chunks.iter().enumerate() {
+    if is(chunk, b"fcTL") {
+      frame_count += 1;
+      last_frame_control = Some(index);
+      match FrameControl::try_from((chunk.chunk_type(), chunk.data())) {
+        Ok(frame) if !frame.fits(width, height) => problems.push((Some(index), ApngError::InvalidRegion)),
+        Ok(frame) if last_image_data.is_none() && !covers_image(&frame) => {
+          problems.push((Some(index), ApngError::InvalidDefaultRegion))
+        }
+        Ok(_) => {}
+        Err(error) => problems.push((Some(index), error)),
+      }
+    } else if is(chunk, b"IDAT") {
+      last_image_data = Some(index);
+    } else if is(chunk, b"fdAT") && last_frame_control <= last_image_data {
+      problems.push((Some(index), ApngError::MissingFrameControl));
+    }
+    let Some(found) = sequence_number(chunk.chunk_type(), chunk.data()) else {
+      if is(chunk, b"fdAT") {
+        problems.push((Some(index), ApngError::InvalidLength(*chunk.chunk_type(), chunk.data().len())));
+      }
+      continue;
+    };
+    if animation.is_none() {
+      problems.push((Some(index), ApngError::NotAnimated));
+    }
+    if found != expected {
+      problems.push((Some(index), ApngError::OutOfSequence { expected, found }));
+    }
+    expected = found.wrapping_add(1);
+  }
+  if let Some(control) = control {
+    if control.num_frames != frame_count {
+      problems.push((None, ApngError::FrameCount {
+        declared: control.num_frames,
+        actual: frame_count,
+      }));
+    }
+  }
+  problems
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame_control(sequence_number: u32) -> FrameControl {
+    FrameControl {
+      sequence_number,
+      width: 4,
+      height: 2,
+      x_offset: 0,
+      y_offset: 0,
+      delay_num: 1,
+      delay_den: 10,
+      dispose_op: DisposeOp::None,
+      blend_op: BlendOp::Source,
+    }
+  }
+
+  fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+  }
+
+  fn frame_data(sequence_number: u32, data: &[u8]) -> Chunk {
+    let mut chunk_data = sequence_number.to_be_bytes().to_vec();
+    chunk_data.extend(data);
+    chunk("fdAT", &chunk_data)
+  }
+
+  fn validate_owned(chunks: &[Chunk], width: u32, height: u32) -> Vec<(Option<usize>, ApngError)> {
+    let chunks: Vec<ChunkRef<'_>> = chunks.iter().map(ChunkRef::from).collect();
+    validate(&chunks, width, height)
+  }
+
+  fn animation() -> Vec<Chunk> {
+    vec![
+      chunk("IHDR", &[0, 0, 0, 4, 0, 0, 0, 2, 8, 2, 0, 0, 0]),
+      AnimationControl { num_frames: 2, num_plays: 0 }.to_chunk(),
+      frame_control(0).to_chunk(),
+      chunk("IDAT", b"first"),
+      frame_control(1).to_chunk(),
+      frame_data(2, b"sec"),
+      frame_data(3, b"ond"),
+      chunk("IEND", b""),
+    ]
+  }
+
+  #[test]
+  fn test_control_round_trip() {
+    let control = AnimationControl { num_frames: 3, num_plays: 2 };
+    assert_eq!(AnimationControl::try_from(&control.to_chunk()).unwrap(), control);
+    let frame = frame_control(7);
+    let chunk = frame.to_chunk();
+    assert_eq!(chunk.length(), 26);
+    assert_eq!(FrameControl::try_from(&chunk).unwrap(), frame);
+    assert_eq!(frame.delay(), Duration::from_millis(100));
+    assert_eq!(frame.to_string(), "4x2 at (0, 0), 100 ms, dispose none, blend source");
+  }
+
+  #[test]
+  fn test_invalid_controls() {
+    assert!(matches!(
+      AnimationControl::try_from(&chunk("acTL", &[0; 8])),
+      Err(ApngError::NoFrames)
+    ));
+    assert!(matches!(
+      FrameControl::try_from(&chunk("fcTL", &[0; 25])),
+      Err(ApngError::InvalidLength(_, 25))
+    ));
+    let mut data = frame_control(0).to_chunk().data().to_vec();
+    data[24] = 3;
+    assert!(matches!(
+      FrameControl::try_from(&chunk("fcTL", &data)),
+      Err(ApngError::InvalidDisposeOp(3))
+    ));
+  }
+
+  #[test]
+  fn test_frames() {
+    let chunks = animation();
+    let frames: Vec<Frame> = frames(&chunks).collect::<result::Result<_, _>>().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].index, 2);
+    assert!(frames[0].is_default_image);
+    assert_eq!(frames[0].image_data(), b"first");
+    assert_eq!(frames[1].control.sequence_number, 1);
+    assert!(!frames[1].is_default_image);
+    assert_eq!(frames[1].image_data(), b"second");
+  }
+
+  #[test]
+  fn test_validate() {
+    assert!(validate_owned(&animation(), 4, 2).is_empty());
+    let mut chunks = animation();
+    chunks[5] = frame_data(5, b"sec");
+    let problems = validate_owned(&chunks, 2, 2);
+    let messages: Vec<String> = problems.iter().map(|(_, error)| error.to_string()).collect();
+    assert_eq!(
+      messages,
+      [
+        "The frame lies outside the image",
+        "The frame lies outside the image",
+        "Expected sequence number 2 but found 5",
+        "Expected sequence number 6 but found 3",
+      ]
+    );
+    chunks.remove(4);
+    let problems = validate_owned(&chunks, 4, 2);
+    assert!(matches!(problems[0], (Some(4), ApngError::MissingFrameControl)));
+    assert!(matches!(
+      problems.last(),
+      Some((None, ApngError::FrameCount { declared: 2, actual: 1 }))
+    ));
+  }
+
+  #[test]
+  fn test_validate_default_image_region() {
+    let mut chunks = animation();
+    chunks[2] = FrameControl { width: 2, ..frame_control(0) }.to_chunk();
+    assert!(matches!(validate_owned(&chunks, 4, 2)[..], [(Some(2), ApngError::InvalidDefaultRegion)]));
+    chunks[2] = FrameControl { x_offset: 1, width: 3, ..frame_control(0) }.to_chunk();
+    assert!(matches!(validate_owned(&chunks, 4, 2)[..], [(Some(2), ApngError::InvalidDefaultRegion)]));
+    // frames after the image data may cover part of the image
+    chunks[2] = frame_control(0).to_chunk();
+    chunks[4] = FrameControl { x_offset: 1, width: 3, ..frame_control(1) }.to_chunk();
+    assert!(validate_owned(&chunks, 4, 2).is_empty());
+  }
+}
@@ -1,20 +1,92 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use pngme::batch::{self, BatchError};
+use pngme::commands;
+use pngme::lsb::Channels;
+use pngme::png::ChunkPosition;
+use pngme::strip::Selector;
+
+use crate::output::Format;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
   #[command(subcommand)]
   pub command: Commands,
+  /// Print sentences or one JSON object
+  #[clap(long, global = true, value_enum, default_value_t)]
+  pub format: Format,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
   Encode(EncodeArgs),
   Decode(DecodeArgs),
+  Embed(EmbedArgs),
+  Extract(ExtractArgs),
   Remove(RemoveArgs),
+  Strip(StripArgs),
+  Repair(RepairArgs),
   Print(PrintArgs),
+  Validate(ValidateArgs),
+  Keygen(KeygenArgs),
+}
+
+/// Where a message is hidden
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+  /// In a chunk of the given type
+  #[default]
+  Chunk,
+  /// In the least significant bits of the pixels, the chunk type is ignored
+  Lsb,
+}
+
+/// How the changed png is saved
+#[derive(Args, Debug)]
+pub struct WriteArgs {
+  /// Keep the replaced file under its name followed by this suffix, like .bak
+  #[clap(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+  pub backup: Option<String>,
+  /// Keep the permissions and modification time of the input file
+  #[clap(long)]
+  pub preserve: bool,
+  /// Report the changes without writing anything
+  #[clap(long)]
+  pub dry_run: bool,
+  /// Keep unknown unsafe-to-copy chunks even when critical chunks change
+  #[clap(long)]
+  pub keep_unsafe: bool,
+}
+
+/// Which files are processed besides the file path
+#[derive(Args, Debug)]
+pub struct BatchArgs {
+  /// Another png, glob pattern or directory to process the same way, may be repeated
+  #[clap(long = "file")]
+  pub files: Vec<PathBuf>,
+  /// Process the png files found in directories and their subdirectories
+  #[clap(short, long)]
+  pub recursive: bool,
+  /// Number of files processed at once, one per core by default
+  #[clap(short, long)]
+  pub jobs: Option<usize>,
+}
+
+impl BatchArgs {
+  /// Whether several files may be processed, with a report for each of them
+  pub fn is_batch(&self, file_path: &Path) -> bool {
+    !self.files.is_empty() || self.recursive || batch::is_pattern(file_path)
+  }
+
+  /// The files named by the file path and the other files, patterns and directories
+  pub fn expand(&self, file_path: &Path) -> Result<Vec<PathBuf>, BatchError> {
+    let mut paths = vec![file_path.to_path_buf()];
+    paths.extend(self.files.iter().cloned());
+    batch::expand(&paths, self.recursive)
+  }
 }
 
 #[derive(Args, Debug)]
@@ -24,10 +96,44 @@ pub struct EncodeArgs {
   pub file_path: PathBuf,
   #[clap(value_parser)]
   pub chunk_type: String,
-  #[clap(value_parser)]
-  pub message: String,
+  #[clap(value_parser, required_unless_present = "message_file")]
+  pub message: Option<String>,
+  /// Where to save the result, the PNG file itself by default, - for stdout
   #[clap(value_parser)]
   pub output: Option<PathBuf>,
+  /// Read the message from this file, - for stdin, instead of the message argument
+  #[clap(long, conflicts_with = "message")]
+  pub message_file: Option<PathBuf>,
+  /// Where to insert the chunk: before-iend, before-idat, after-idat, before-frame-N or a chunk
+  /// index
+  #[clap(long, default_value = "before-iend")]
+  pub position: ChunkPosition,
+  /// Encrypt the message with a passphrase
+  #[clap(long)]
+  pub encrypt: bool,
+  /// Passphrase used with --encrypt, prompted for when missing
+  #[clap(long, requires = "encrypt")]
+  pub passphrase: Option<String>,
+  /// Encrypt the message for the X25519 public key in this file, may be repeated
+  #[clap(long = "recipient", conflicts_with = "encrypt")]
+  pub recipients: Vec<PathBuf>,
+  /// Split the message across several chunks holding at most this many bytes each
+  #[clap(long)]
+  pub max_chunk_size: Option<usize>,
+  /// Hide the message in a chunk or in the pixels
+  #[clap(long, value_enum, default_value_t)]
+  pub mode: Mode,
+  /// Channels carrying the message in lsb mode, like rgb or la, all but alpha by default
+  #[clap(long)]
+  pub channels: Option<Channels>,
+  /// Store the message as standard text metadata under this keyword, the chunk type being
+  /// tEXt, zTXt (compressed) or iTXt (UTF-8)
+  #[clap(long, conflicts_with_all = ["encrypt", "recipients", "max_chunk_size"])]
+  pub text_keyword: Option<String>,
+  #[command(flatten)]
+  pub write: WriteArgs,
+  #[command(flatten)]
+  pub batch: BatchArgs,
 }
 #[derive(Args, Debug)]
 pub struct DecodeArgs {
@@ -35,6 +141,60 @@ pub struct DecodeArgs {
   pub file_path: PathBuf,
   #[clap(value_parser)]
   pub chunk_type: String,
+  /// Passphrase of an encrypted message, prompted for when missing
+  #[clap(long)]
+  pub passphrase: Option<String>,
+  /// Identity file able to open a message encrypted for recipients, may be repeated
+  #[clap(long = "identity")]
+  pub identities: Vec<PathBuf>,
+  /// Look for the message in a chunk or in the pixels
+  #[clap(long, value_enum, default_value_t)]
+  pub mode: Mode,
+  /// Channels carrying the message in lsb mode, like rgb or la, all but alpha by default
+  #[clap(long)]
+  pub channels: Option<Channels>,
+  #[command(flatten)]
+  pub batch: BatchArgs,
+}
+/// Embeds a binary file into a PNG file
+#[derive(Args, Debug)]
+pub struct EmbedArgs {
+  #[clap(value_parser)]
+  pub file_path: PathBuf,
+  #[clap(value_parser)]
+  pub chunk_type: String,
+  /// The file to embed, - for stdin
+  #[clap(value_parser)]
+  pub attachment_path: PathBuf,
+  /// Where to save the result, the PNG file itself by default, - for stdout
+  #[clap(short, long)]
+  pub output: Option<PathBuf>,
+  /// MIME type stored with the file
+  #[clap(long)]
+  pub mime_type: Option<String>,
+  /// Do not store the name of the file
+  #[clap(long)]
+  pub no_filename: bool,
+  /// Split the file across several chunks holding at most this many bytes each
+  #[clap(long)]
+  pub max_chunk_size: Option<usize>,
+  /// Where to insert the chunk: before-iend, before-idat, after-idat, before-frame-N or a chunk
+  /// index
+  #[clap(long, default_value = "before-iend")]
+  pub position: ChunkPosition,
+  #[command(flatten)]
+  pub write: WriteArgs,
+}
+/// Extracts a binary file embedded into a PNG file
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+  #[clap(value_parser)]
+  pub file_path: PathBuf,
+  #[clap(value_parser)]
+  pub chunk_type: String,
+  /// Where to save the extracted file, - for stdout
+  #[clap(short, long)]
+  pub output: PathBuf,
 }
 #[derive(Args, Debug)]
 pub struct RemoveArgs {
@@ -42,9 +202,75 @@ pub struct RemoveArgs {
   pub file_path: PathBuf,
   #[clap(value_parser)]
   pub chunk_type: String,
+  #[command(flatten)]
+  pub write: WriteArgs,
+  #[command(flatten)]
+  pub batch: BatchArgs,
+}
+/// Removes ancillary chunks, all of them by default
+#[derive(Args, Debug)]
+pub struct StripArgs {
+  #[clap(value_parser)]
+  pub file_path: PathBuf,
+  /// Where to save the result, the PNG file itself by default, - for stdout
+  #[clap(short, long)]
+  pub output: Option<PathBuf>,
+  /// Keep chunks of this type or category (text, time, color, exif, private), may be repeated
+  #[clap(long)]
+  pub keep: Vec<Selector>,
+  /// Only remove chunks of this type or category, may be repeated
+  #[clap(long)]
+  pub remove: Vec<Selector>,
+  #[command(flatten)]
+  pub write: WriteArgs,
+  #[command(flatten)]
+  pub batch: BatchArgs,
+}
+/// Reports damaged chunks and writes a repaired copy
+#[derive(Args, Debug)]
+pub struct RepairArgs {
+  #[clap(value_parser)]
+  pub file_path: PathBuf,
+  /// Where to save the repaired file, image.repaired.png next to image.png by default, - for
+  /// stdout
+  #[clap(short, long)]
+  pub output: Option<PathBuf>,
+  /// Keep chunks with a crc mismatch and recompute their crc instead of dropping them
+  #[clap(long)]
+  pub fix_crc: bool,
+  #[command(flatten)]
+  pub write: WriteArgs,
+}
+
+impl RepairArgs {
+  /// The output, or a copy next to the PNG file, so the damaged file is kept
+  pub fn output(&self) -> PathBuf {
+    if let Some(output) = &self.output {
+      return output.clone();
+    }
+    if commands::is_stdio(&self.file_path) {
+      return self.file_path.clone();
+    }
+    let stem = self.file_path.file_stem().unwrap_or_default().to_string_lossy();
+    self.file_path.with_file_name(format!("{}.repaired.png", stem))
+  }
 }
+
 #[derive(Args, Debug)]
 pub struct PrintArgs {
   #[clap(value_parser)]
   pub file_path: PathBuf,
+  #[command(flatten)]
+  pub batch: BatchArgs,
+}
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+  #[clap(value_parser)]
+  pub file_path: PathBuf,
+}
+/// Generates an identity file and its public key file with a .pub suffix
+#[derive(Args, Debug)]
+pub struct KeygenArgs {
+  #[clap(value_parser)]
+  pub identity_path: PathBuf,
 }
@@ -0,0 +1,216 @@
+//! Binary files stored in a chunk
+//!
+//! An attachment is a header followed by the raw file bytes. All integers are big endian:
+//!
+//! | field         | size          | value                                 |
+//! |---------------|---------------|---------------------------------------|
+//! | magic         | 4             | `PMat`                                |
+//! | version       | 1             | `1`                                   |
+//! | size          | 8             | length of the file data               |
+//! | filename_len  | 2             | `0` when there is no filename         |
+//! | filename      | filename_len  | UTF-8 file name without directories   |
+//! | mime_type_len | 2             | `0` when there is no MIME type        |
+//! | mime_type     | mime_type_len | UTF-8 MIME type                       |
+//! | data          | size          | file data                             |
+
+use std::{error, fmt, result};
+
+/// Marks the start of an attachment
+pub const MAGIC: [u8; 4] = *b"PMat";
+pub const VERSION: u8 = 1;
+
+/// Attachment errors
+#[derive(Debug)]
+pub enum AttachmentError {
+  /// The data does not start with the attachment magic
+  NotAttachment,
+  UnsupportedVersion(u8),
+  /// The attachment ends before the expected number of bytes
+  Truncated(usize, usize),
+  /// The data is longer than the size in the header
+  TrailingData(usize),
+  /// The filename or MIME type is not valid UTF-8
+  InvalidUTF8Metadata,
+  /// The filename or MIME type is longer than 65535 bytes
+  MetadataTooLong(usize),
+}
+
+impl error::Error for AttachmentError {}
+
+impl fmt::Display for AttachmentError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AttachmentError::NotAttachment => write!(f, "The data is not an attachment"),
+      AttachmentError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported attachment version {}", version)
+      }
+      AttachmentError::Truncated(expected, actual) => write!(
+        f,
+        "Expected {} bytes of attachment but found {}",
+        expected, actual
+      ),
+      AttachmentError::TrailingData(len) => {
+        write!(f, "Found {} unexpected bytes after the attachment", len)
+      }
+      AttachmentError::InvalidUTF8Metadata => write!(f, "Attachment metadata is invalid UTF8"),
+      AttachmentError::MetadataTooLong(len) => write!(
+        f,
+        "Attachment metadata is {} bytes long, the maximum is {}",
+        len,
+        u16::MAX
+      ),
+    }
+  }
+}
+
+/// A file with its optional name and MIME type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+  pub filename: Option<String>,
+  pub mime_type: Option<String>,
+  pub data: Vec<u8>,
+}
+
+impl Attachment {
+  /// Whether the data starts like an attachment
+  pub fn is_attachment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+  }
+
+  /// Serializes the attachment as chunk data
+  pub fn to_bytes(&self) -> result::Result<Vec<u8>, AttachmentError> {
+    let mut bytes: Vec<u8> = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend((self.data.len() as u64).to_be_bytes());
+    for field in [&self.filename, &self.mime_type] {
+      let field = field.as_deref().unwrap_or_default().as_bytes();
+      let len = u16::try_from(field.len()).map_err(|_| AttachmentError::MetadataTooLong(field.len()))?;
+      bytes.extend(len.to_be_bytes());
+      bytes.extend(field);
+    }
+    bytes.extend(&self.data);
+    Ok(bytes)
+  }
+}
+
+/// Reads the fields of an attachment in order
+struct Fields<'a> {
+  bytes: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Fields<'a> {
+  fn take(&mut self, len: usize) -> result::Result<&'a [u8], AttachmentError> {
+    let end = self.offset.saturating_add(len);
+    let field = self
+      .bytes
+      .get(self.offset..end)
+      .ok_or(AttachmentError::Truncated(end, self.bytes.len()))?;
+    self.offset = end;
+    Ok(field)
+  }
+
+  fn take_string(&mut self) -> result::Result<Option<String>, AttachmentError> {
+    let len = self.take(2)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    let field = self.take(len)?;
+    match field.is_empty() {
+      true => Ok(None),
+      false => String::from_utf8(field.to_vec())
+        .map(Some)
+        .map_err(|_| AttachmentError::InvalidUTF8Metadata),
+    }
+  }
+}
+
+impl TryFrom<&[u8]> for Attachment {
+  type Error = AttachmentError;
+  fn try_from(bytes: &[u8]) -> result::Result<Attachment, AttachmentError> {
+    if !Attachment::is_attachment(bytes) {
+      return Err(AttachmentError::NotAttachment);
+    }
+    let mut fields = Fields { bytes, offset: MAGIC.len() };
+    match fields.take(1)?[0] {
+      VERSION => {}
+      version => return Err(AttachmentError::UnsupportedVersion(version)),
+    }
+    let size: [u8; 8] = fields.take(8)?.try_into().map_err(|_| AttachmentError::NotAttachment)?;
+    let size = usize::try_from(u64::from_be_bytes(size)).unwrap_or(usize::MAX);
+    let filename = fields.take_string()?;
+    let mime_type = fields.take_string()?;
+    let data = fields.take(size)?.to_vec();
+    if fields.offset != bytes.len() {
+      return Err(AttachmentError::TrailingData(bytes.len() - fields.offset));
+    }
+    Ok(Attachment { filename, mime_type, data })
+  }
+}
+
+impl fmt::Display for Attachment {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.filename.as_deref().unwrap_or("unnamed file"))?;
+    if let Some(mime_type) = &self.mime_type {
+      write!(f, " ({})", mime_type)?;
+    }
+    write!(f, ", {} bytes", self.data.len())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn testing_attachment() -> Attachment {
+    Attachment {
+      filename: Some("report.pdf".to_owned()),
+      mime_type: Some("application/pdf".to_owned()),
+      data: vec![0, 159, 146, 150, 255],
+    }
+  }
+
+  #[test]
+  fn test_attachment_round_trip() {
+    let attachment = testing_attachment();
+    let bytes = attachment.to_bytes().unwrap();
+    assert!(Attachment::is_attachment(&bytes));
+    assert_eq!(bytes.len(), 4 + 1 + 8 + 2 + 10 + 2 + 15 + 5);
+    assert_eq!(Attachment::try_from(bytes.as_slice()).unwrap(), attachment);
+  }
+
+  #[test]
+  fn test_attachment_without_metadata() {
+    let attachment = Attachment {
+      filename: None,
+      mime_type: None,
+      data: vec![1, 2, 3],
+    };
+    let bytes = attachment.to_bytes().unwrap();
+    assert_eq!(Attachment::try_from(bytes.as_slice()).unwrap(), attachment);
+    assert_eq!(attachment.to_string(), "unnamed file, 3 bytes");
+  }
+
+  #[test]
+  fn test_invalid_attachment() {
+    let bytes = testing_attachment().to_bytes().unwrap();
+    for len in 0..bytes.len() {
+      assert!(Attachment::try_from(&bytes[..len]).is_err());
+    }
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(matches!(
+      Attachment::try_from(trailing.as_slice()),
+      Err(AttachmentError::TrailingData(1))
+    ));
+    let mut huge = bytes;
+    huge[5..13].copy_from_slice(&u64::MAX.to_be_bytes());
+    assert!(matches!(
+      Attachment::try_from(huge.as_slice()),
+      Err(AttachmentError::Truncated(_, _))
+    ));
+  }
+
+  #[test]
+  fn test_attachment_display() {
+    assert_eq!(testing_attachment().to_string(), "report.pdf (application/pdf), 5 bytes");
+  }
+}
@@ -0,0 +1,146 @@
+//! Running a command over many png files at once
+//!
+//! [`expand`] turns paths, glob patterns and directories into a list of files, and [`run`] calls a
+//! command on each of them in parallel, keeping the results in the order of the files.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, result};
+
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use walkdir::WalkDir;
+
+/// Errors while listing or processing files
+#[derive(Debug)]
+pub enum BatchError {
+  /// A glob pattern is malformed
+  InvalidPattern(String, glob::PatternError),
+  /// A glob pattern matches no file
+  NoMatch(String),
+  /// A directory was given without `recursive`
+  IsDirectory(PathBuf),
+  /// A directory cannot be read
+  Walk(walkdir::Error),
+  /// The worker threads cannot be started
+  ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl error::Error for BatchError {}
+
+impl fmt::Display for BatchError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BatchError::InvalidPattern(pattern, error) => write!(f, "Invalid pattern {}: {}", pattern, error),
+      BatchError::NoMatch(pattern) => write!(f, "No file matches {}", pattern),
+      BatchError::IsDirectory(path) => write!(f, "{} is a directory, use --recursive", path.display()),
+      BatchError::Walk(error) => write!(f, "Cannot read directory: {}", error),
+      BatchError::ThreadPool(error) => write!(f, "Cannot start workers: {}", error),
+    }
+  }
+}
+
+/// Whether a path is a glob pattern rather than the name of a file
+pub fn is_pattern(path: &Path) -> bool {
+  let path = path.to_string_lossy();
+  path.contains(['*', '?', '[']) && !Path::new(path.as_ref()).exists()
+}
+
+/// Whether a file has a png extension
+fn is_png(path: &Path) -> bool {
+  path
+    .extension()
+    .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// The png files under a directory, sorted by path
+fn walk(directory: &Path) -> result::Result<Vec<PathBuf>, BatchError> {
+  let mut files = Vec::new();
+  for entry in WalkDir::new(directory).sort_by_file_name() {
+    let entry = entry.map_err(BatchError::Walk)?;
+    if entry.file_type().is_file() && is_png(entry.path()) {
+      files.push(entry.into_path());
+    }
+  }
+  Ok(files)
+}
+
+/// Expands paths, glob patterns and directories into the files they name
+///
+/// Directories are searched for png files when `recursive` is set and rejected otherwise. Plain
+/// paths are kept as they are, even when they do not exist, and every file is listed once.
+pub fn expand(paths: &[PathBuf], recursive: bool) -> result::Result<Vec<PathBuf>, BatchError> {
+  let mut files = Vec::new();
+  let mut add = |path: PathBuf| -> result::Result<(), BatchError> {
+    match path.is_dir() {
+      true if recursive => files.extend(walk(&path)?),
+      true => return Err(BatchError::IsDirectory(path)),
+      false => files.push(path),
+    }
+    Ok(())
+  };
+  for path in paths {
+    if !is_pattern(path) {
+      add(path.clone())?;
+      continue;
+    }
+    let pattern = path.to_string_lossy();
+    let matches = glob::glob(&pattern).map_err(|error| BatchError::InvalidPattern(pattern.to_string(), error))?;
+    let mut found = false;
+    for path in matches.flatten() {
+      if path.is_dir() && !recursive {
+        continue;
+      }
+      found = true;
+      add(path)?;
+    }
+    if !found {
+      return Err(BatchError::NoMatch(pattern.to_string()));
+    }
+  }
+  let mut seen = HashSet::new();
+  files.retain(|path| seen.insert(path.clone()));
+  Ok(files)
+}
+
+/// Calls `command` on every file with `jobs` threads, one per core by default
+///
+/// The results are in the order of `files`.
+pub fn run<T, F>(files: &[PathBuf], jobs: Option<usize>, command: F) -> result::Result<Vec<T>, BatchError>
+where
+  T: Send,
+  F: Fn(&Path) -> T + Sync,
+{
+  let pool = ThreadPoolBuilder::new()
+    .num_threads(jobs.unwrap_or(0))
+    .build()
+    .map_err(BatchError::ThreadPool)?;
+  Ok(pool.install(|| files.par_iter().map(|path| command(path)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::TestingFile;
+
+  #[test]
+  fn test_expand() {
+    let directory = TestingFile::directory("expand", &["b.png", "a.png", "c.txt", "sub/d.PNG"]);
+    let root = &directory.0;
+    let files = expand(&[root.join("*.png")], false).unwrap();
+    assert_eq!(files, vec![root.join("a.png"), root.join("b.png")]);
+    let files = expand(&[root.clone(), root.join("a.png")], true).unwrap();
+    assert_eq!(files, vec![root.join("a.png"), root.join("b.png"), root.join("sub/d.PNG")]);
+    assert!(matches!(expand(std::slice::from_ref(root), false), Err(BatchError::IsDirectory(_))));
+    assert!(matches!(expand(&[root.join("*.gif")], false), Err(BatchError::NoMatch(_))));
+    assert_eq!(expand(&[root.join("missing.png")], false).unwrap(), vec![root.join("missing.png")]);
+  }
+
+  #[test]
+  fn test_run() {
+    let files: Vec<PathBuf> = (0..20).map(|index| PathBuf::from(index.to_string())).collect();
+    let names = run(&files, Some(4), |path| path.display().to_string()).unwrap();
+    let expected: Vec<String> = (0..20).map(|index| index.to_string()).collect();
+    assert_eq!(names, expected);
+  }
+}
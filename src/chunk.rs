@@ -3,7 +3,8 @@ use std::{fmt, result };
 
 use crate::chunk_type::ChunkType;
 
-#[derive(Debug)]
+/// A png chunk: length, type, data and crc
+#[derive(Debug, Clone)]
 pub struct Chunk {
   length: u32,
   chunk_type: ChunkType,
@@ -11,40 +12,229 @@ pub struct Chunk {
   crc: u32,
 }
 
-impl Chunk {
-  pub fn try_from_sequence(bytes: &[u8]) -> result::Result<Vec<Chunk>, ChunkError> {
-    let length_len = 4usize;
-    let crc_len = 4usize;
-    let chunk_type_len = 4usize;
-    let mut chunks: Vec<Chunk> = Vec::new();
-    let mut chunk_data = bytes;
-    loop {
-      if chunk_data.len() <= 0 {
-        break;
+/// Size of the length and chunk type fields preceding the chunk data
+pub(crate) const HEADER_LEN: usize = 8;
+/// Size of the crc field following the chunk data
+pub(crate) const CRC_LEN: usize = 4;
+
+pub(crate) static CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// How a chunk sequence parser reacts to a malformed chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+  /// Fail on the first malformed chunk
+  #[default]
+  Strict,
+  /// Skip chunks with a bad type or crc, stop at broken framing and keep what was parsed
+  Lenient,
+}
+
+/// A chunk borrowing its type and data from the buffer it was parsed from
+///
+/// Its crc is only checked on demand by [`ChunkRef::verify_crc`] or when converting it to an
+/// owned [`Chunk`], so that reading a few chunks of a large file skips hashing the others.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkRef<'a> {
+  chunk_type: ChunkType,
+  chunk_data: &'a [u8],
+  crc: u32,
+}
+
+impl<'a> ChunkRef<'a> {
+  /// Borrows consecutive chunks, checking their framing and types but not their crc
+  ///
+  /// Every chunk is returned with its byte offset in `bytes`. The iterator ends after a chunk
+  /// whose length field does not fit in the input.
+  pub fn sequence(bytes: &'a [u8]) -> ChunkRefs<'a> {
+    ChunkRefs {
+      bytes,
+      offset: 0,
+      index: 0,
+    }
+  }
+
+  /// Length of the chunk data
+  pub fn length(&self) -> u32 {
+    self.chunk_data.len() as u32
+  }
+
+  /// The crc stored in the chunk, which may not match its content
+  pub fn crc(&self) -> u32 {
+    self.crc
+  }
+
+  pub fn chunk_type(&self) -> &ChunkType {
+    &self.chunk_type
+  }
+
+  pub fn data(&self) -> &'a [u8] {
+    self.chunk_data
+  }
+
+  /// Checks the stored crc against the chunk type and data
+  pub fn verify_crc(&self) -> result::Result<(), ChunkError> {
+    let crc = Chunk::create_crc(&self.chunk_type, self.chunk_data);
+    match crc == self.crc {
+      true => Ok(()),
+      false => Err(ChunkError::InvalidCrc(crc, self.crc)),
+    }
+  }
+
+  /// Copies the chunk into an owned chunk once its crc is verified
+  pub fn to_chunk(&self) -> result::Result<Chunk, ChunkError> {
+    self.verify_crc()?;
+    Ok(Chunk {
+      length: self.length(),
+      chunk_type: self.chunk_type,
+      chunk_data: self.chunk_data.to_vec(),
+      crc: self.crc,
+    })
+  }
+}
+
+/// Borrows an owned chunk, whose crc is already known to match
+impl<'a> From<&'a Chunk> for ChunkRef<'a> {
+  fn from(chunk: &'a Chunk) -> ChunkRef<'a> {
+    ChunkRef {
+      chunk_type: chunk.chunk_type,
+      chunk_data: &chunk.chunk_data,
+      crc: chunk.crc,
+    }
+  }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+  type Error = ChunkError;
+  fn try_from(bytes: &'a [u8]) -> result::Result<ChunkRef<'a>, ChunkError> {
+    if bytes.len() < HEADER_LEN + CRC_LEN {
+      return Err(ChunkError::UnexpectedEof(HEADER_LEN + CRC_LEN, bytes.len()));
+    }
+    let chunk_data_length = read_u32(bytes, 0)?;
+    let type_bytes: [u8; 4] = bytes[4..HEADER_LEN]
+      .try_into()
+      .map_err(|_| ChunkError::InvalidChunkType)?;
+    let chunk_type = ChunkType::try_from(type_bytes).map_err(|_| ChunkError::InvalidChunkType)?;
+    let idx = bytes.len() - CRC_LEN;
+    let chunk_data = &bytes[HEADER_LEN..idx];
+    let crc = read_u32(bytes, idx)?;
+    let actual_chunk_data_length = u32::try_from(chunk_data.len()).unwrap_or(u32::MAX);
+    if chunk_data_length != actual_chunk_data_length {
+      return Err(ChunkError::InvalidChunkDatLength(
+        chunk_data_length,
+        actual_chunk_data_length,
+      ));
+    }
+    Ok(ChunkRef {
+      chunk_type,
+      chunk_data,
+      crc,
+    })
+  }
+}
+
+/// Iterator over the chunks of a byte sequence, created by [`ChunkRef::sequence`]
+pub struct ChunkRefs<'a> {
+  bytes: &'a [u8],
+  offset: usize,
+  index: usize,
+}
+
+impl<'a> Iterator for ChunkRefs<'a> {
+  type Item = result::Result<(usize, ChunkRef<'a>), ParseError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let remaining = self.bytes.get(self.offset..).filter(|remaining| !remaining.is_empty())?;
+    let offset = self.offset;
+    let parse_error = |error| ParseError {
+      offset,
+      index: self.index,
+      chunk_type: Chunk::peek_type(remaining),
+      error,
+    };
+    let frame_len = match Chunk::frame_len(remaining) {
+      Ok(frame_len) => frame_len,
+      Err(error) => {
+        self.offset = self.bytes.len();
+        return Some(Err(parse_error(error)));
       }
-      let (length, _chunk_string) = chunk_data.split_at(length_len);
-      let length: [u8; 4] = length.try_into().unwrap();
-      let chunk_data_len: usize = u32::from_be_bytes(length).try_into().unwrap();
-      let (chunk_bytes, chunk_left) =
-        chunk_data.split_at(length_len + chunk_data_len + chunk_type_len + crc_len);
-      chunk_data = chunk_left;
-      match Chunk::try_from(&chunk_bytes.to_vec()) {
+    };
+    let chunk = ChunkRef::try_from(&remaining[..frame_len]).map_err(parse_error);
+    self.offset += frame_len;
+    self.index += 1;
+    Some(chunk.map(|chunk| (offset, chunk)))
+  }
+}
+
+/// Chunks recovered from a byte sequence with the problems met along the way
+#[derive(Debug, Default)]
+pub struct ParsedChunks {
+  pub chunks: Vec<Chunk>,
+  pub diagnostics: Vec<ParseError>,
+  /// Index in the sequence of each chunk of `chunks`, as malformed chunks are left out
+  pub indices: Vec<usize>,
+}
+
+impl Chunk {
+  /// Maximum value of the length field allowed by the png spec
+  pub const MAX_LENGTH: u32 = (1 << 31) - 1;
+
+  /// Parses consecutive chunks, failing on the first malformed one
+  pub fn try_from_sequence(bytes: &[u8]) -> result::Result<Vec<Chunk>, ParseError> {
+    Chunk::parse_sequence(bytes, ParseMode::Strict).map(|parsed| parsed.chunks)
+  }
+
+  /// Parses consecutive chunks without panicking on truncated or corrupted input
+  pub fn parse_sequence(bytes: &[u8], mode: ParseMode) -> result::Result<ParsedChunks, ParseError> {
+    let mut parsed = ParsedChunks::default();
+    for (index, chunk) in ChunkRef::sequence(bytes).enumerate() {
+      let chunk = chunk.and_then(|(offset, chunk)| {
+        chunk.to_chunk().map_err(|error| ParseError {
+          offset,
+          index,
+          chunk_type: Some(chunk.chunk_type),
+          error,
+        })
+      });
+      match chunk {
         Ok(chunk) => {
-          chunks.push(chunk);
-        }
-        Err(_) => {
-          break;
+          parsed.chunks.push(chunk);
+          parsed.indices.push(index);
         }
+        Err(error) if mode == ParseMode::Lenient => parsed.diagnostics.push(error),
+        Err(error) => return Err(error),
       }
     }
-    Ok(chunks)
+    Ok(parsed)
+  }
+
+  /// Total size of the chunk starting at the beginning of `bytes`, checked against the input
+  fn frame_len(bytes: &[u8]) -> result::Result<usize, ChunkError> {
+    let length = read_u32(bytes, 0)?;
+    if length > Chunk::MAX_LENGTH {
+      return Err(ChunkError::InvalidChunkLength(length));
+    }
+    let frame_len = HEADER_LEN + length as usize + CRC_LEN;
+    if bytes.len() < frame_len {
+      return Err(ChunkError::UnexpectedEof(frame_len, bytes.len()));
+    }
+    Ok(frame_len)
+  }
+
+  /// Chunk type of the chunk starting at the beginning of `bytes`, if it is readable
+  fn peek_type(bytes: &[u8]) -> Option<ChunkType> {
+    let type_bytes: [u8; 4] = bytes.get(4..HEADER_LEN)?.try_into().ok()?;
+    ChunkType::try_from(type_bytes).ok()
   }
 
-  pub fn create_crc(chunk_type: &ChunkType, chunk_data: &Vec<u8>) -> u32 {
-    let crc_struct = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-    crc_struct.checksum(&[chunk_type.bytes().as_slice(), chunk_data.as_slice()].concat())
+  /// Crc of the chunk type and data
+  pub fn create_crc(chunk_type: &ChunkType, chunk_data: &[u8]) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(&chunk_type.bytes());
+    digest.update(chunk_data);
+    digest.finalize()
   }
 
+  /// Creates a chunk, computing its length and crc
   pub fn new(chunk_type: ChunkType, chunk_data: Vec<u8>) -> Chunk {
     let crc = Chunk::create_crc(&chunk_type, &chunk_data);
     Chunk {
@@ -54,6 +244,7 @@ impl Chunk {
       crc,
     }
   }
+  /// Length of the chunk data
   pub fn length(&self) -> u32 {
     self.length
   }
@@ -66,10 +257,16 @@ impl Chunk {
     &self.chunk_type
   }
 
+  pub fn data(&self) -> &[u8] {
+    &self.chunk_data
+  }
+
+  /// Chunk data decoded as UTF-8
   pub fn data_as_string(&self) -> result::Result<String, ChunkError> {
     String::from_utf8(self.chunk_data.clone()).map_err(|_| ChunkError::InvalidUTF8DataString)
   }
 
+  /// The chunk serialized as it appears in a png file
   pub fn as_bytes(&self) -> Vec<u8> {
     self
       .length
@@ -83,41 +280,35 @@ impl Chunk {
   }
 }
 
-impl TryFrom<&Vec<u8>> for Chunk {
+fn read_u32(bytes: &[u8], offset: usize) -> result::Result<u32, ChunkError> {
+  let field: [u8; 4] = bytes
+    .get(offset..offset + 4)
+    .and_then(|field| field.try_into().ok())
+    .ok_or(ChunkError::UnexpectedEof(offset + 4, bytes.len()))?;
+  Ok(u32::from_be_bytes(field))
+}
+
+impl TryFrom<&[u8]> for Chunk {
   type Error = ChunkError;
-  fn try_from(bytes: &Vec<u8>) -> result::Result<Chunk, ChunkError> {
-    let chunk_data_length = u32::from_be_bytes(bytes[..4].try_into().unwrap());
-    let type_bytes: [u8; 4] = bytes[4..8].try_into().unwrap();
-    let chunk_type = ChunkType::try_from(type_bytes).unwrap();
-    let idx = bytes.len() - 4;
-    let chunk_data = &bytes[8..idx].try_into().unwrap();
-    let crc = u32::from_be_bytes(bytes[idx..].try_into().unwrap());
-    let calc_crc = Chunk::create_crc(&chunk_type, &chunk_data);
-    let actual_chunk_data_length = chunk_data.len().try_into().unwrap();
-    if chunk_data_length != actual_chunk_data_length {
-      return Err(ChunkError::InvalidChunkDatLength(
-        chunk_data_length,
-        actual_chunk_data_length,
-      ));
-    }
-    if calc_crc != crc {
-      return Err(ChunkError::InvalidCrc(calc_crc, crc));
-    }
-    Ok(Chunk::new(chunk_type, chunk_data.to_vec()))
+  fn try_from(bytes: &[u8]) -> result::Result<Chunk, ChunkError> {
+    ChunkRef::try_from(bytes)?.to_chunk()
   }
 }
 
 impl fmt::Display for Chunk {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{}", self.data_as_string().unwrap())
+    write!(f, "{}", String::from_utf8_lossy(&self.chunk_data))
   }
 }
 
+/// Chunk errors
 #[derive(Debug)]
 pub enum ChunkError {
   InvalidCrc(u32, u32),
   InvalidChunkDatLength(u32, u32),
   InvalidChunkType,
+  InvalidChunkLength(u32),
+  UnexpectedEof(usize, usize),
   InvalidUTF8DataString,
 }
 
@@ -137,11 +328,50 @@ impl fmt::Display for ChunkError {
         expected, actual
       ),
       ChunkError::InvalidChunkType => write!(f, "Invalid chunk type"),
+      ChunkError::InvalidChunkLength(length) => write!(
+        f,
+        "Invalid chunk length {}, the maximum is {}",
+        length,
+        Chunk::MAX_LENGTH
+      ),
+      ChunkError::UnexpectedEof(expected, actual) => write!(
+        f,
+        "Unexpected end of input. Expected {} bytes but found {}",
+        expected, actual
+      ),
       ChunkError::InvalidUTF8DataString => write!(f, "Chunk Data is invalid UTF8 encode string"),
     }
   }
 }
 
+/// A chunk parse failure located in the input
+#[derive(Debug)]
+pub struct ParseError {
+  /// Byte offset of the start of the failing chunk
+  pub offset: usize,
+  /// Index of the failing chunk in the sequence
+  pub index: usize,
+  /// Type of the failing chunk, when it could be read
+  pub chunk_type: Option<ChunkType>,
+  pub error: ChunkError,
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let chunk_type = self
+      .chunk_type
+      .as_ref()
+      .map_or("unknown type".to_owned(), |chunk_type| chunk_type.to_string());
+    write!(
+      f,
+      "Failed to parse chunk {} ({}) at byte offset {}: {}",
+      self.index, chunk_type, self.offset, self.error
+    )
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -251,6 +481,75 @@ mod tests {
     assert!(chunk.is_err());
   }
 
+  fn testing_sequence() -> Vec<u8> {
+    ["FrSt", "miDl", "LASt"]
+      .iter()
+      .flat_map(|chunk_type| {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), b"message".to_vec()).as_bytes()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_parse_sequence() {
+    let chunks = Chunk::try_from_sequence(&testing_sequence()).unwrap();
+    assert_eq!(chunks.len(), 3);
+  }
+
+  #[test]
+  fn test_parse_truncated_sequence() {
+    let bytes = testing_sequence();
+    for len in 0..bytes.len() {
+      let _ = Chunk::parse_sequence(&bytes[..len], ParseMode::Lenient);
+      let _ = Chunk::try_from_sequence(&bytes[..len]);
+    }
+    let error = Chunk::try_from_sequence(&bytes[..bytes.len() - 1]).unwrap_err();
+    assert_eq!(error.index, 2);
+    assert_eq!(error.offset, 38);
+    assert_eq!(error.chunk_type.unwrap().to_string(), "LASt");
+    assert!(matches!(error.error, ChunkError::UnexpectedEof(19, 18)));
+  }
+
+  #[test]
+  fn test_parse_bogus_length() {
+    let mut bytes = testing_sequence();
+    bytes[19..23].copy_from_slice(&u32::MAX.to_be_bytes());
+    let error = Chunk::try_from_sequence(&bytes).unwrap_err();
+    assert_eq!(error.index, 1);
+    assert!(matches!(error.error, ChunkError::InvalidChunkLength(u32::MAX)));
+  }
+
+  #[test]
+  fn test_parse_lenient_sequence() {
+    let mut bytes = testing_sequence();
+    // corrupt the crc of the second chunk
+    bytes[37] ^= 0xff;
+    let error = Chunk::try_from_sequence(&bytes).unwrap_err();
+    assert!(matches!(error.error, ChunkError::InvalidCrc(_, _)));
+
+    let parsed = Chunk::parse_sequence(&bytes, ParseMode::Lenient).unwrap();
+    assert_eq!(parsed.chunks.len(), 2);
+    assert_eq!(parsed.diagnostics.len(), 1);
+    assert_eq!(parsed.diagnostics[0].offset, 19);
+    assert_eq!(parsed.chunks[1].chunk_type().to_string(), "LASt");
+  }
+
+  #[test]
+  fn test_chunk_ref_sequence() {
+    let mut bytes = testing_sequence();
+    bytes[37] ^= 0xff;
+    let chunks: Vec<(usize, ChunkRef)> = ChunkRef::sequence(&bytes).collect::<result::Result<_, _>>().unwrap();
+    let offsets: Vec<usize> = chunks.iter().map(|(offset, _)| *offset).collect();
+    assert_eq!(offsets, [0, 19, 38]);
+    let (_, second) = chunks[1];
+    assert_eq!(second.data().as_ptr(), bytes[27..].as_ptr());
+    assert!(matches!(second.verify_crc(), Err(ChunkError::InvalidCrc(_, _))));
+    assert!(second.to_chunk().is_err());
+    let third = chunks[2].1.to_chunk().unwrap();
+    assert_eq!(third.crc(), chunks[2].1.crc());
+    assert_eq!(third.data(), b"message");
+  }
+
   #[test]
   pub fn test_chunk_trait_impls() {
     let data_length: u32 = 42;
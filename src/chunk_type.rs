@@ -1,5 +1,7 @@
 use std::{convert::TryFrom, error, fmt, str::FromStr, result};
 
+use crate::registry::{self, Definition};
+
 const CONDITION: u8 = 1 << 5;
 
 /// Chunk type errors
@@ -34,12 +36,14 @@ impl fmt::Display for ChunkTypeError {
   }
 }
 
-#[derive(Debug, PartialEq)]
+/// A four letter chunk type code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ChunkType {
   bytes: [u8; 4],
 }
 
 impl ChunkType {
+  /// Whether all bytes are ASCII letters
   pub fn is_valid_source(bytes: &[u8; 4]) -> bool {
     bytes
       .iter()
@@ -49,20 +53,35 @@ impl ChunkType {
   pub fn bytes(&self) -> [u8; 4] {
     self.bytes
   }
+  /// Ancillary bit: decoders must understand critical chunks
   pub fn is_critical(&self) -> bool {
     self.bytes[0] & CONDITION == 0
   }
+  /// Private bit: public chunks are defined by the spec or registered
   pub fn is_public(&self) -> bool {
     self.bytes[1] & CONDITION == 0
   }
+  /// Reserved bit: must be uppercase in this version of png
   pub fn is_reserved_bit_valid(&self) -> bool {
     self.bytes[2] & CONDITION == 0
   }
+  /// Safe-to-copy bit: editors may keep the chunk after modifying critical chunks
   pub fn is_safe_to_copy(&self) -> bool {
     self.bytes[3] & CONDITION != 0
   }
   pub fn is_valid(&self) -> bool {
-    return self.is_reserved_bit_valid();
+    self.is_reserved_bit_valid()
+  }
+  /// Whether editors may keep the chunk after changing critical chunks
+  ///
+  /// Critical, safe-to-copy and known chunk types may be kept, while unknown chunks whose
+  /// safe-to-copy bit is not set may depend on the critical chunks and must be dropped.
+  pub fn is_copy_allowed(&self) -> bool {
+    self.is_critical() || self.is_safe_to_copy() || self.definition().is_some()
+  }
+  /// The spec definition of the chunk type, `None` for private and unknown chunk types
+  pub fn definition(&self) -> Option<&'static Definition> {
+    registry::lookup(self)
   }
 }
 
@@ -1,54 +1,903 @@
-use std::fs;
-use std::str::FromStr;
-
-use crate::args::{DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs};
-use crate::chunk::{Chunk};
-use crate::chunk_type::{ChunkType};
-use crate::png::Png;
-use crate::{Result};
-
-/// Encodes a message into a PNG file and saves the result
-pub fn encode(args: &EncodeArgs) -> Result<()> {
-  let EncodeArgs { file_path, chunk_type, message, output } = args;
-  let mut png = Png::from_file(file_path.to_path_buf())?;
-  let chunk_type = ChunkType::from_str(chunk_type.as_str())?;
-  let chunk = Chunk::new(chunk_type, message.as_bytes().to_vec());
-  png.append_chunk(chunk);
-  let path = match output {
-    Some(path) => path,
-    None => file_path, 
-  };
-  fs::write(path, png.as_bytes())?;
-  Ok(())
+use std::fs::{self, File};
+use std::fs::FileTimes;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, process, result};
+
+use crate::attachment::Attachment;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crypto::{self, CryptoError, Scheme, StaticSecret};
+use crate::fragment::{self, Fragment, FragmentError};
+use crate::chunk::ParseError;
+use crate::image_data::ImageDataOptions;
+use crate::lsb::{self, Channels, LsbError};
+use crate::mapped::MappedFile;
+use crate::png::{ChunkPosition, Png, PngError, PngRef};
+use crate::repair::{self, Damage, Repair, RepairError};
+use crate::stream::{ChunkHeader, PngReader, PngWriter};
+use crate::strip::StripFilter;
+use crate::validation::{self, Violation};
+use crate::{Error, Result};
+
+type FileReader = PngReader<BufReader<Source>>;
+type FileWriter = PngWriter<Destination>;
+
+/// Reasons why no message could be decoded
+#[derive(Debug)]
+pub enum DecodeError {
+  /// The file does not exist or cannot be opened
+  CannotOpen(PathBuf),
+  /// The file does not start with the png signature
+  NotPng,
+  /// A chunk of the file is malformed
+  Malformed(ParseError),
+  /// There is no chunk of the given type
+  ChunkNotFound(String),
+  /// The pixels do not hold a hidden message
+  NoHiddenMessage,
+  InvalidFragments(FragmentError),
+  /// The pixels cannot hold a message
+  Lsb(LsbError),
+  /// The message cannot be decrypted with the given passphrase or identities
+  Crypto(CryptoError),
+  /// The passphrase could not be read
+  Passphrase(Error),
+  /// The message is not valid UTF-8 text
+  NotUtf8,
+  Io(io::Error),
+  Png(PngError),
 }
 
-/// Searches for a message hidden in a PNG file and prints the message if one is found
-pub fn decode(args: &DecodeArgs) -> Option<String> {
-  let DecodeArgs { file_path, chunk_type } = args;
-  let png = Png::from_file(file_path.to_path_buf()).ok()?;
-  let chunk = png.chunk_by_type(chunk_type.as_str())?;
-  chunk.data_as_string().ok()
+impl error::Error for DecodeError {}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::CannotOpen(file_path) => write!(f, "Cannot open {}", file_path.display()),
+      DecodeError::NotPng => write!(f, "The file is not a png"),
+      DecodeError::Malformed(error) => write!(f, "Malformed png: {}", error),
+      DecodeError::ChunkNotFound(chunk_type) => write!(f, "The chunk {} is not found", chunk_type),
+      DecodeError::NoHiddenMessage => write!(f, "The pixels hold no message"),
+      DecodeError::InvalidFragments(error) => write!(f, "Invalid fragments: {}", error),
+      DecodeError::Lsb(error) => write!(f, "{}", error),
+      DecodeError::Crypto(error) => write!(f, "Cannot decrypt the message: {}", error),
+      DecodeError::Passphrase(error) => write!(f, "Cannot read the passphrase: {}", error),
+      DecodeError::NotUtf8 => write!(f, "The message is not valid UTF8 text"),
+      DecodeError::Io(error) => write!(f, "IO error: {}", error),
+      DecodeError::Png(error) => write!(f, "{}", error),
+    }
+  }
 }
 
-/// Removes a chunk from a PNG file and saves the result
-pub fn remove(args: &RemoveArgs) -> Result<()> {
-  let RemoveArgs { file_path, chunk_type } = args;
-  let mut png = Png::from_file(file_path.to_path_buf())?;
-  png.remove_chunk(chunk_type)?;
-  fs::write(file_path, png.as_bytes())?;
-  Ok(())
+impl From<PngError> for DecodeError {
+  fn from(error: PngError) -> DecodeError {
+    match error {
+      PngError::PngFileOpenFail(file_path) => DecodeError::CannotOpen(file_path),
+      PngError::HeaderInValid => DecodeError::NotPng,
+      PngError::ChunksInvalid(error) => DecodeError::Malformed(error),
+      PngError::Io(error) => DecodeError::Io(error),
+      error => DecodeError::Png(error),
+    }
+  }
+}
+
+/// Whether a path stands for stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+  path.as_os_str() == "-"
+}
+
+/// Where a png is read from: a file, or stdin for `-`
+enum Source {
+  File(File),
+  Stdin(io::Stdin),
+}
+
+impl Read for Source {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      Source::File(file) => file.read(buf),
+      Source::Stdin(stdin) => stdin.read(buf),
+    }
+  }
+}
+
+fn open(file_path: &Path) -> result::Result<FileReader, PngError> {
+  let source = match is_stdio(file_path) {
+    true => Source::Stdin(io::stdin()),
+    false => Source::File(File::open(file_path).map_err(|_| PngError::PngFileOpenFail(file_path.to_path_buf()))?),
+  };
+  PngReader::new(BufReader::new(source))
+}
+
+/// Reads a whole file, or stdin for `-`
+pub fn read_input(file_path: &Path) -> io::Result<Vec<u8>> {
+  match is_stdio(file_path) {
+    true => {
+      let mut bytes = Vec::new();
+      io::stdin().read_to_end(&mut bytes)?;
+      Ok(bytes)
+    }
+    false => fs::read(file_path),
+  }
+}
+
+fn read(file_path: &Path) -> result::Result<Vec<u8>, PngError> {
+  read_input(file_path).map_err(|_| PngError::PngFileOpenFail(file_path.to_path_buf()))
+}
+
+/// The bytes of a png being read: a mapped file, or stdin read into memory
+enum Input {
+  Mapped(MappedFile),
+  Buffer(Vec<u8>),
+}
+
+impl Deref for Input {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      Input::Mapped(mapped) => mapped,
+      Input::Buffer(bytes) => bytes,
+    }
+  }
+}
+
+/// Maps a file for read-only commands, falling back to reading it whole when it cannot be
+/// mapped, or reads stdin for `-`
+fn load(file_path: &Path) -> result::Result<Input, PngError> {
+  if !is_stdio(file_path) {
+    // SAFETY: pngme replaces files by renaming rather than writing into them, and the readme
+    // warns that other programs must not change a file while a read-only command maps it
+    if let Ok(mapped) = unsafe { MappedFile::open(file_path) } {
+      return Ok(Input::Mapped(mapped));
+    }
+  }
+  read(file_path).map(Input::Buffer)
+}
+
+fn read_png(file_path: &Path) -> result::Result<Png, PngError> {
+  Png::try_from(read(file_path)?.as_slice())
+}
+
+/// How commands save the png they change
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+  /// Keep the file being replaced under its own name followed by this suffix
+  pub backup: Option<String>,
+  /// Give the new file the permissions, access and modification times of the input file
+  pub preserve: bool,
+  /// Do everything but write to disk
+  pub dry_run: bool,
+  /// Copy unknown unsafe-to-copy chunks even when critical chunks change
+  pub keep_unsafe: bool,
+}
+
+/// Where a png is written: a file, stdout once complete, or nowhere in a dry run
+enum Destination {
+  File(BufWriter<File>),
+  Stdout(Vec<u8>),
+  DryRun(io::Sink),
 }
 
-/// Prints all of the chunks in a PNG file
-pub fn print_chunks(args: &PrintArgs) -> Result<()> {
-  let PrintArgs { file_path  } = args;
-  let png = Png::from_file(file_path.to_path_buf())?;
-  for chunk in png.chunks() {
-    if let Ok(msg) = chunk.data_as_string() {
-      if !msg.trim().is_empty() {
-        println!("the chunk type is {}, the msg is {}" , chunk.chunk_type(), msg);
+impl Write for Destination {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      Destination::File(file) => file.write(buf),
+      Destination::Stdout(bytes) => bytes.write(buf),
+      Destination::DryRun(sink) => sink.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Destination::File(file) => file.flush(),
+      Destination::Stdout(_) => Ok(()),
+      Destination::DryRun(sink) => sink.flush(),
+    }
+  }
+}
+
+impl Destination {
+  /// Flushes the file contents to the disk, or the whole png to `stdout`
+  fn sync(self, stdout: &mut dyn Write) -> io::Result<()> {
+    match self {
+      Destination::File(file) => file.into_inner().map_err(|error| error.into_error())?.sync_all(),
+      Destination::Stdout(bytes) => {
+        stdout.write_all(&bytes)?;
+        stdout.flush()
       }
+      Destination::DryRun(_) => Ok(()),
     }
   }
+}
+
+/// Copies the permissions, access and modification times of `source` to `target`
+fn preserve_metadata(source: &Path, target: &Path) -> io::Result<()> {
+  let metadata = fs::metadata(source)?;
+  let times = FileTimes::new()
+    .set_accessed(metadata.accessed()?)
+    .set_modified(metadata.modified()?);
+  File::options().write(true).open(target)?.set_times(times)?;
+  // last, as read-only permissions would keep the file from being opened for the times
+  fs::set_permissions(target, metadata.permissions())
+}
+
+/// Keeps the current content of `path` under its name followed by `suffix`
+fn backup(path: &Path, suffix: &str) -> io::Result<()> {
+  if !path.exists() {
+    return Ok(());
+  }
+  let mut backup_path = path.as_os_str().to_owned();
+  backup_path.push(suffix);
+  let backup_path = PathBuf::from(backup_path);
+  if backup_path == path {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, "the backup suffix is empty"));
+  }
+  if backup_path.exists() {
+    fs::remove_file(&backup_path)?;
+  }
+  fs::hard_link(path, &backup_path).or_else(|_| fs::copy(path, &backup_path).map(|_| ()))
+}
+
+/// Writes a png through `write` into a temporary file next to `output` which then replaces it
+///
+/// The permissions of an existing `output` are kept. `source` is the input file whose metadata is
+/// preserved instead when requested. Nothing is written in
+/// a dry run, but `write` still runs to report the changes. An `output` of `-` writes the png to
+/// `stdout` once it is complete, without backup or metadata.
+fn replace<F>(source: &Path, output: &Path, options: &WriteOptions, stdout: &mut dyn Write, write: F) -> Result<()>
+where
+  F: FnOnce(&mut FileWriter) -> Result<()>,
+{
+  let destination = match (options.dry_run, is_stdio(output)) {
+    (true, _) => Some(Destination::DryRun(io::sink())),
+    (false, true) => Some(Destination::Stdout(Vec::new())),
+    (false, false) => None,
+  };
+  if let Some(destination) = destination {
+    let mut writer = PngWriter::new(destination)?;
+    write(&mut writer)?;
+    writer.finish()?.sync(stdout)?;
+    return Ok(());
+  }
+  let mut temp_path = output.as_os_str().to_owned();
+  temp_path.push(format!(".{}.tmp", process::id()));
+  let temp_path = PathBuf::from(temp_path);
+  let file = File::options().write(true).create_new(true).open(&temp_path)?;
+  let result = PngWriter::new(Destination::File(BufWriter::new(file)))
+    .map_err(Error::from)
+    .and_then(|mut writer| {
+      write(&mut writer)?;
+      writer.finish()?.sync(stdout)?;
+      if options.preserve && !is_stdio(source) {
+        preserve_metadata(source, &temp_path)?;
+      } else if let Ok(metadata) = fs::metadata(output) {
+        // the temporary file has the default permissions, which may expose a private output
+        fs::set_permissions(&temp_path, metadata.permissions())?;
+      }
+      if let Some(suffix) = &options.backup {
+        backup(output, suffix)?;
+      }
+      Ok(fs::rename(&temp_path, output)?)
+    });
+  if result.is_err() {
+    let _ = fs::remove_file(&temp_path);
+    return result;
+  }
+  let directory = match output.parent() {
+    Some(parent) if !parent.as_os_str().is_empty() => parent,
+    _ => Path::new("."),
+  };
+  let _ = File::open(directory).and_then(|directory| directory.sync_all());
   Ok(())
 }
+
+/// Streams `input` through `edit` into a temporary file which then replaces `output`
+fn rewrite<F>(input: &Path, output: &Path, options: &WriteOptions, edit: F) -> Result<()>
+where
+  F: FnOnce(&mut FileReader, &mut FileWriter) -> Result<()>,
+{
+  let mut reader = open(input)?;
+  replace(input, output, options, &mut io::stdout(), |writer| edit(&mut reader, writer))
+}
+
+/// Saves a png held in memory and read from `input` to `output`, or `stdout` for `-`
+///
+/// Returns the chunks dropped by [`Png::discard_unsafe`] unless `keep_unsafe` is set.
+fn save(
+  png: &mut Png,
+  input: &Path,
+  output: &Path,
+  options: &WriteOptions,
+  stdout: &mut dyn Write,
+) -> Result<Vec<Chunk>> {
+  let discarded = match options.keep_unsafe {
+    true => Vec::new(),
+    false => png.discard_unsafe(),
+  };
+  replace(input, output, options, stdout, |writer| {
+    for chunk in png.chunks() {
+      writer.write_chunk(chunk)?;
+    }
+    Ok(())
+  })?;
+  Ok(discarded)
+}
+
+/// Copies a chunk, unless `discard_unsafe` is set and the chunk must not be copied once critical
+/// chunks change, in which case it is read into `discarded`
+fn copy_or_discard(
+  reader: &mut FileReader,
+  writer: &mut FileWriter,
+  header: ChunkHeader,
+  discard_unsafe: bool,
+  discarded: &mut Vec<Chunk>,
+) -> Result<()> {
+  if discard_unsafe && !header.chunk_type.is_copy_allowed() {
+    discarded.push(reader.read_body(header)?);
+    return Ok(());
+  }
+  Ok(writer.copy_chunk(reader, &header)?)
+}
+
+/// Inserts a chunk into the png at `file_path` and saves the result to `output`
+///
+/// Returns the index the chunk was inserted at and the chunks discarded as in [`encode_chunks`].
+pub fn encode(
+  file_path: &Path,
+  output: &Path,
+  chunk: Chunk,
+  position: ChunkPosition,
+  options: &WriteOptions,
+) -> Result<(usize, Vec<Chunk>)> {
+  encode_chunks(file_path, output, vec![chunk], position, options)
+}
+
+/// Inserts consecutive chunks into the png at `file_path` and saves the result to `output`
+///
+/// Returns the index the first chunk was inserted at. When critical chunks are inserted, the
+/// chunks [`Png::discard_unsafe`] would drop are left out and returned as well, unless
+/// `keep_unsafe` is set.
+pub fn encode_chunks(
+  file_path: &Path,
+  output: &Path,
+  chunks: Vec<Chunk>,
+  position: ChunkPosition,
+  options: &WriteOptions,
+) -> Result<(usize, Vec<Chunk>)> {
+  let discard_unsafe = !options.keep_unsafe && chunks.iter().any(|chunk| chunk.chunk_type().is_critical());
+  let mut discarded = Vec::new();
+  let mut chunks = Some(chunks);
+  let mut inserted_at = 0;
+  rewrite(file_path, output, options, |reader, writer| {
+    let mut previous: Option<ChunkType> = None;
+    let mut index = 0;
+    let mut frame = 0;
+    loop {
+      let header = reader.next_header()?;
+      let next = header.as_ref().map(|header| &header.chunk_type);
+      if position.matches(index, frame, previous.as_ref(), next) {
+        if let Some(chunks) = chunks.take() {
+          for chunk in &chunks {
+            writer.write_chunk(chunk)?;
+          }
+          inserted_at = index;
+        }
+      }
+      let Some(header) = header else { break };
+      let chunk_type = header.chunk_type;
+      copy_or_discard(reader, writer, header, discard_unsafe, &mut discarded)?;
+      if chunk_type.bytes() == *b"fcTL" {
+        frame += 1;
+      }
+      previous = Some(chunk_type);
+      index += 1;
+    }
+    match chunks {
+      Some(_) => Err(position.not_found(index, frame).into()),
+      None => Ok(()),
+    }
+  })?;
+  Ok((inserted_at, discarded))
+}
+
+/// Returns the data of the first chunk of the given type in a PNG file
+///
+/// When that chunk is a fragment, the payload is reassembled from all the chunks of that type.
+pub fn find_payload(file_path: &Path, chunk_type: &str) -> result::Result<Option<Vec<u8>>, DecodeError> {
+  let bytes = load(file_path)?;
+  let png = PngRef::parse(&bytes)?;
+  let mut chunks = png.chunks_by_type(chunk_type);
+  let first = match chunks.next() {
+    Some(chunk) => chunk?,
+    None => return Ok(None),
+  };
+  if !Fragment::is_fragment(first.data()) {
+    return Ok(Some(first.data().to_vec()));
+  }
+  let mut fragments = vec![first.data()];
+  for chunk in chunks {
+    fragments.push(chunk?.data());
+  }
+  let fragments = fragments.into_iter().filter(|data| Fragment::is_fragment(data));
+  let payload = fragment::reassemble(fragments).map_err(DecodeError::InvalidFragments)?;
+  Ok(Some(payload))
+}
+
+/// Decodes the message hidden in a chunk of a PNG file
+///
+/// Messages encrypted for recipients are opened with any of the `identities`, and messages
+/// encrypted with a passphrase with the result of `passphrase`, which is only called for them.
+pub fn decode<F>(
+  file_path: &Path,
+  chunk_type: &str,
+  identities: &[StaticSecret],
+  passphrase: F,
+) -> result::Result<String, DecodeError>
+where
+  F: FnOnce() -> Result<String>,
+{
+  let Some(payload) = find_payload(file_path, chunk_type)? else {
+    return Err(DecodeError::ChunkNotFound(chunk_type.to_owned()));
+  };
+  open_payload(payload, identities, passphrase)
+}
+
+/// Decrypts a payload when it is sealed and decodes it as text
+fn open_payload<F>(payload: Vec<u8>, identities: &[StaticSecret], passphrase: F) -> result::Result<String, DecodeError>
+where
+  F: FnOnce() -> Result<String>,
+{
+  let payload = match crypto::is_sealed(&payload) {
+    true => match crypto::scheme(&payload).map_err(DecodeError::Crypto)? {
+      Scheme::Passphrase => {
+        let passphrase = passphrase().map_err(DecodeError::Passphrase)?;
+        crypto::open(&payload, passphrase.as_bytes()).map_err(DecodeError::Crypto)?
+      }
+      Scheme::Recipients => crypto::open_for(&payload, identities).map_err(DecodeError::Crypto)?,
+    },
+    false => payload,
+  };
+  String::from_utf8(payload).map_err(|_| DecodeError::NotUtf8)
+}
+
+/// Hides a payload in the pixels of the png at `file_path` and saves the result to `output`
+///
+/// The image data is re-encoded into new IDAT chunks in place of the original ones, and the
+/// chunks dropped by [`Png::discard_unsafe`] are returned unless `keep_unsafe` is set.
+pub fn encode_lsb(
+  file_path: &Path,
+  output: &Path,
+  payload: &[u8],
+  channels: &Channels,
+  options: &WriteOptions,
+) -> Result<Vec<Chunk>> {
+  let mut png = read_png(file_path)?;
+  let header = png.image_header()?;
+  let mut raw = png.image_data()?;
+  lsb::embed(&header, &mut raw, channels, payload)?;
+  let image_data_options = ImageDataOptions {
+    compression_level: 9,
+    ..ImageDataOptions::default()
+  };
+  png.set_image_data(&raw, &image_data_options)?;
+  save(&mut png, file_path, output, options, &mut io::stdout())
+}
+
+/// Decodes the message hidden in the pixels of a PNG file
+///
+/// Encrypted messages are opened as in [`decode`].
+pub fn decode_lsb<F>(
+  file_path: &Path,
+  channels: &Channels,
+  identities: &[StaticSecret],
+  passphrase: F,
+) -> result::Result<String, DecodeError>
+where
+  F: FnOnce() -> Result<String>,
+{
+  let bytes = load(file_path)?;
+  let png = PngRef::parse(&bytes)?;
+  let payload = lsb::extract(&png.image_header()?, &png.image_data()?, channels).map_err(DecodeError::Lsb)?;
+  match payload {
+    Some(payload) => open_payload(payload, identities, passphrase),
+    None => Err(DecodeError::NoHiddenMessage),
+  }
+}
+
+/// Searches for a file embedded in a PNG file and returns it if one is found
+pub fn extract(file_path: &Path, chunk_type: &str) -> Result<Option<Attachment>> {
+  match find_payload(file_path, chunk_type)? {
+    Some(payload) => Ok(Some(Attachment::try_from(payload.as_slice())?)),
+    None => Ok(None),
+  }
+}
+
+/// Removes the first chunk of the given type from a PNG file, saves the result and returns the chunk
+///
+/// When that chunk is a fragment, the other fragments of the same payload are removed as well.
+/// Removing a critical chunk also discards chunks as in [`encode_chunks`], which are returned
+/// after the removed chunk. Nothing is written when there is no such chunk.
+pub fn remove(file_path: &Path, chunk_type: &str, options: &WriteOptions) -> Result<(Chunk, Vec<Chunk>)> {
+  let critical = chunk_type.parse::<ChunkType>().is_ok_and(|chunk_type| chunk_type.is_critical());
+  let discard_unsafe = !options.keep_unsafe && critical;
+  let mut discarded = Vec::new();
+  let mut removed: Option<Chunk> = None;
+  rewrite(file_path, file_path, options, |reader, writer| {
+    let mut fragment_id = None;
+    while let Some(header) = reader.next_header()? {
+      if header.chunk_type.to_string() != chunk_type || (removed.is_some() && fragment_id.is_none()) {
+        copy_or_discard(reader, writer, header, discard_unsafe, &mut discarded)?;
+        continue;
+      }
+      let chunk = reader.read_body(header)?;
+      let id = Fragment::try_from(chunk.data()).ok().map(|fragment| fragment.id);
+      match removed {
+        None => {
+          fragment_id = id;
+          removed = Some(chunk);
+        }
+        Some(_) if id.is_some() && id == fragment_id => {}
+        Some(_) => writer.write_chunk(&chunk)?,
+      }
+    }
+    match removed {
+      Some(_) => Ok(()),
+      None => Err(PngError::ChunkNotFound(chunk_type.to_owned()).into()),
+    }
+  })?;
+  let removed = removed.ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_owned()))?;
+  Ok((removed, discarded))
+}
+
+/// Removes the ancillary chunks selected by the filter from a PNG file, saves the result to
+/// `output` and returns the removed chunks
+pub fn strip(file_path: &Path, output: &Path, filter: &StripFilter, options: &WriteOptions) -> Result<Vec<Chunk>> {
+  let mut png = read_png(file_path)?;
+  let removed = png.strip(filter);
+  save(&mut png, file_path, output, options, &mut io::stdout())?;
+  Ok(removed)
+}
+
+/// Recovers the chunks of a damaged PNG file and reports what was wrong
+///
+/// The recovered chunks are saved to `output` unless it is the file itself and nothing was
+/// repaired. Chunks with a crc mismatch are kept with a recomputed crc when `fix_crc` is set and
+/// dropped otherwise, except for critical chunks, which make the repair fail without `fix_crc`.
+/// Offsets in the report count from the start of the file.
+pub fn repair(file_path: &Path, output: &Path, fix_crc: bool, options: &WriteOptions) -> Result<Vec<Repair>> {
+  repair_to(file_path, output, fix_crc, options, &mut io::stdout())
+}
+
+fn repair_to(
+  file_path: &Path,
+  output: &Path,
+  fix_crc: bool,
+  options: &WriteOptions,
+  stdout: &mut dyn Write,
+) -> Result<Vec<Repair>> {
+  let bytes = read(file_path)?;
+  if bytes.get(..Png::STANDARD_HEADER.len()) != Some(&Png::STANDARD_HEADER[..]) {
+    return Err(PngError::HeaderInValid.into());
+  }
+  let repaired = repair::repair(&bytes[Png::STANDARD_HEADER.len()..], fix_crc);
+  let dropped_critical = repaired.repairs.iter().find_map(|repair| match (repair.damage, repair.chunk_type) {
+    (Damage::CrcMismatch(..), Some(chunk_type)) if chunk_type.is_critical() && !repair.fixed => {
+      Some((repair.index, chunk_type))
+    }
+    _ => None,
+  });
+  if let Some((index, chunk_type)) = dropped_critical {
+    return Err(RepairError::CriticalCrcMismatch(index, chunk_type).into());
+  }
+  if !repaired.repairs.is_empty() || output != file_path || is_stdio(output) {
+    let mut png = Png::from_chunks(repaired.chunks);
+    if repaired.repairs.iter().any(Repair::is_critical) {
+      png.mark_critical_changed();
+    }
+    save(&mut png, file_path, output, options, stdout)?;
+  }
+  let repairs = repaired
+    .repairs
+    .into_iter()
+    .map(|repair| Repair {
+      offset: repair.offset + Png::STANDARD_HEADER.len(),
+      ..repair
+    })
+    .collect();
+  Ok(repairs)
+}
+
+/// Maps a PNG file without copying its chunks and passes it to `inspect`
+///
+/// Only the chunks `inspect` reads through [`PngRef::verify`] and its other accessors have their
+/// crc checked.
+pub fn inspect<T, F>(file_path: &Path, inspect: F) -> Result<T>
+where
+  F: FnOnce(&PngRef<'_>) -> Result<T>,
+{
+  let bytes = load(file_path)?;
+  inspect(&PngRef::parse(&bytes)?)
+}
+
+/// Checks a PNG file against the png spec, reporting malformed chunks and broken rules
+pub fn validate(file_path: &Path) -> Result<Vec<Violation>> {
+  let bytes = load(file_path)?;
+  let parsed = PngRef::parse_lenient(&bytes)?;
+  let mut violations: Vec<Violation> = parsed.diagnostics.into_iter().map(Violation::from).collect();
+  // malformed chunks are left out of the png, so its indices are mapped back to the file
+  violations.extend(validation::validate(parsed.png.chunks()).into_iter().map(|violation| Violation {
+    index: violation.index.map(|index| parsed.indices[index]),
+    ..violation
+  }));
+  Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::TestingFile;
+  use std::str::FromStr;
+
+  fn no_passphrase() -> Result<String> {
+    Err("not encrypted".into())
+  }
+
+  fn has_chunk(file_path: &Path, chunk_type: &str) -> bool {
+    Png::from_file(file_path.to_path_buf()).unwrap().chunk_by_type(chunk_type).is_some()
+  }
+
+  fn hello_chunk() -> Chunk {
+    Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec())
+  }
+
+  #[test]
+  fn test_decode() {
+    let file = TestingFile::new("decode", &[("ruSt", b"hello"), ("IEND", b"")]);
+    assert_eq!(decode(&file.0, "ruSt", &[], no_passphrase).unwrap(), "hello");
+  }
+
+  #[test]
+  fn test_decode_errors() {
+    let file = TestingFile::new("decode-errors", &[("ruSt", &[0xff, 0xfe]), ("IEND", b"")]);
+    assert!(matches!(
+      decode(&file.0, "ruSt", &[], no_passphrase),
+      Err(DecodeError::NotUtf8)
+    ));
+    assert!(matches!(
+      decode(&file.0, "teSt", &[], no_passphrase),
+      Err(DecodeError::ChunkNotFound(_))
+    ));
+    let missing = file.0.with_extension("missing");
+    assert!(matches!(
+      decode(&missing, "ruSt", &[], no_passphrase),
+      Err(DecodeError::CannotOpen(_))
+    ));
+    let params = crypto::KdfParams {
+      m_cost: 64,
+      t_cost: 1,
+      p_cost: 1,
+    };
+    let sealed = crypto::seal_with(b"secret", b"passphrase", params).unwrap();
+    let file = TestingFile::new("decode-sealed", &[("ruSt", &sealed)]);
+    assert!(matches!(
+      decode(&file.0, "ruSt", &[], no_passphrase),
+      Err(DecodeError::Passphrase(_))
+    ));
+    assert!(matches!(
+      decode(&file.0, "ruSt", &[], || Ok("wrong".to_owned())),
+      Err(DecodeError::Crypto(CryptoError::Decryption))
+    ));
+  }
+
+  #[test]
+  fn test_decode_not_png() {
+    let file = TestingFile::new("not-png", &[]);
+    fs::write(&file.0, b"not a png at all").unwrap();
+    assert!(matches!(
+      decode(&file.0, "ruSt", &[], no_passphrase),
+      Err(DecodeError::NotPng)
+    ));
+  }
+
+  #[test]
+  fn test_validate_after_malformed_chunk() {
+    let header: &[u8] = &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0];
+    let file = TestingFile::new(
+      "validate",
+      &[("IHDR", header), ("ruSt", b"hello"), ("IDAT", b""), ("gAMA", &[0; 4]), ("IEND", b"")],
+    );
+    let mut bytes = fs::read(&file.0).unwrap();
+    let crc_offset = Png::STANDARD_HEADER.len() + 12 + header.len() + 8 + 5;
+    bytes[crc_offset] ^= 0xff;
+    fs::write(&file.0, bytes).unwrap();
+    let violations = validate(&file.0).unwrap();
+    let located: Vec<_> = violations.iter().map(|violation| (violation.index, violation.rule.name())).collect();
+    assert_eq!(located, [(Some(1), "malformed"), (Some(3), "must_precede")]);
+    assert_eq!(violations[1].chunk_type.unwrap().to_string(), "gAMA");
+  }
+
+  #[test]
+  fn test_replace_file() {
+    let file = TestingFile::new("replace-file", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let output = TestingFile(file.0.with_extension("out.png"));
+    let mut stdout = Vec::new();
+    let write = |writer: &mut FileWriter| Ok(writer.write_chunk(&hello_chunk())?);
+    replace(&file.0, &output.0, &WriteOptions::default(), &mut stdout, write).unwrap();
+    assert!(stdout.is_empty());
+    let png = Png::try_from(fs::read(&output.0).unwrap().as_slice()).unwrap();
+    assert_eq!(png.as_bytes(), Png::from_chunks(vec![hello_chunk()]).as_bytes());
+    let mut temp_path = output.0.clone().into_os_string();
+    temp_path.push(format!(".{}.tmp", process::id()));
+    assert!(!PathBuf::from(temp_path).exists());
+  }
+
+  #[test]
+  fn test_replace_stdout() {
+    let file = TestingFile::new("replace-stdout", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let before = fs::read(&file.0).unwrap();
+    let mut stdout = Vec::new();
+    let write = |writer: &mut FileWriter| Ok(writer.write_chunk(&hello_chunk())?);
+    replace(&file.0, Path::new("-"), &WriteOptions::default(), &mut stdout, write).unwrap();
+    let png = Png::try_from(stdout.as_slice()).unwrap();
+    assert_eq!(png.as_bytes(), Png::from_chunks(vec![hello_chunk()]).as_bytes());
+    assert_eq!(fs::read(&file.0).unwrap(), before);
+    assert!(!Path::new("-").exists());
+  }
+
+  #[test]
+  fn test_repair_always_writes_copies() {
+    let file = TestingFile::new("repair-copies", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let before = fs::read(&file.0).unwrap();
+    let mut stdout = Vec::new();
+    assert!(repair_to(&file.0, &file.0, false, &WriteOptions::default(), &mut stdout).unwrap().is_empty());
+    assert!(stdout.is_empty());
+    assert!(repair_to(&file.0, Path::new("-"), false, &WriteOptions::default(), &mut stdout).unwrap().is_empty());
+    assert_eq!(stdout, before);
+    let output = TestingFile(file.0.with_extension("repaired.png"));
+    assert!(repair(&file.0, &output.0, false, &WriteOptions::default()).unwrap().is_empty());
+    assert_eq!(fs::read(&output.0).unwrap(), before);
+  }
+
+  #[test]
+  fn test_repair_keeps_critical_chunks() {
+    let file = TestingFile::new("repair-keep-critical", &[("IHDR", &[0; 13]), ("IDAT", b"x"), ("IEND", b"")]);
+    let mut bytes = fs::read(&file.0).unwrap();
+    // the crc of IDAT, after the signature and IHDR
+    bytes[8 + 25 + 9] ^= 1;
+    fs::write(&file.0, &bytes).unwrap();
+    let output = TestingFile(file.0.with_extension("repaired.png"));
+    let error = repair(&file.0, &output.0, false, &WriteOptions::default()).unwrap_err();
+    assert!(matches!(
+      error.downcast_ref::<RepairError>(),
+      Some(RepairError::CriticalCrcMismatch(1, chunk_type)) if chunk_type.to_string() == "IDAT"
+    ));
+    assert!(!output.0.exists());
+    assert_eq!(fs::read(&file.0).unwrap(), bytes);
+  }
+
+  #[test]
+  fn test_repair_critical_discards_unsafe() {
+    let chunks: [(&str, &[u8]); 4] = [("IHDR", &[0; 13]), ("ruST", b"unsafe"), ("IDAT", b"x"), ("IEND", b"")];
+    let file = TestingFile::new("repair-critical", &chunks);
+    let mut bytes = fs::read(&file.0).unwrap();
+    // the crc of IDAT, after the signature, IHDR and ruST
+    bytes[8 + 25 + 18 + 9] ^= 1;
+    fs::write(&file.0, &bytes).unwrap();
+    assert_eq!(repair(&file.0, &file.0, true, &WriteOptions::default()).unwrap().len(), 1);
+    let png = Png::from_file(file.0.clone()).unwrap();
+    let types: Vec<String> = png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+    assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+
+    let file = TestingFile::new("repair-ancillary", &[("IHDR", &[0; 13]), ("ruST", b"unsafe"), ("IEND", b"")]);
+    let mut bytes = fs::read(&file.0).unwrap();
+    bytes[8 + 25 + 8 + 6] ^= 1;
+    fs::write(&file.0, &bytes).unwrap();
+    assert_eq!(repair(&file.0, &file.0, true, &WriteOptions::default()).unwrap().len(), 1);
+    assert!(has_chunk(&file.0, "ruST"));
+  }
+
+  #[test]
+  fn test_encode_dry_run() {
+    let file = TestingFile::new("dry-run", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let before = fs::read(&file.0).unwrap();
+    let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+    let options = WriteOptions {
+      dry_run: true,
+      ..Default::default()
+    };
+    assert_eq!(encode(&file.0, &file.0, chunk, ChunkPosition::default(), &options).unwrap().0, 1);
+    assert_eq!(fs::read(&file.0).unwrap(), before);
+  }
+
+  #[test]
+  fn test_encode_backup() {
+    let file = TestingFile::new("backup", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let before = fs::read(&file.0).unwrap();
+    let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+    let options = WriteOptions {
+      backup: Some(".bak".to_owned()),
+      ..Default::default()
+    };
+    encode(&file.0, &file.0, chunk, ChunkPosition::default(), &options).unwrap();
+    let mut backup = file.0.clone().into_os_string();
+    backup.push(".bak");
+    let backup = TestingFile(PathBuf::from(backup));
+    assert_eq!(fs::read(&backup.0).unwrap(), before);
+    assert_eq!(decode(&file.0, "ruSt", &[], no_passphrase).unwrap(), "hello");
+  }
+
+  #[test]
+  fn test_encode_preserve_read_only() {
+    let file = TestingFile::new("preserve", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let mut permissions = fs::metadata(&file.0).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&file.0, permissions).unwrap();
+    let modified = fs::metadata(&file.0).unwrap().modified().unwrap();
+    let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+    let options = WriteOptions {
+      preserve: true,
+      ..Default::default()
+    };
+    encode(&file.0, &file.0, chunk, ChunkPosition::default(), &options).unwrap();
+    let metadata = fs::metadata(&file.0).unwrap();
+    assert!(metadata.permissions().readonly());
+    assert_eq!(metadata.modified().unwrap(), modified);
+    assert_eq!(decode(&file.0, "ruSt", &[], no_passphrase).unwrap(), "hello");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_encode_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    let file = TestingFile::new("keep-permissions", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    fs::set_permissions(&file.0, fs::Permissions::from_mode(0o600)).unwrap();
+    let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+    encode(&file.0, &file.0, chunk, ChunkPosition::default(), &WriteOptions::default()).unwrap();
+    assert_eq!(fs::metadata(&file.0).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(decode(&file.0, "ruSt", &[], no_passphrase).unwrap(), "hello");
+  }
+
+  #[test]
+  fn test_encode_empty_backup_suffix() {
+    let file = TestingFile::new("empty-backup", &[("IHDR", &[0; 13]), ("IEND", b"")]);
+    let before = fs::read(&file.0).unwrap();
+    let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+    let options = WriteOptions {
+      backup: Some(String::new()),
+      ..Default::default()
+    };
+    assert!(encode(&file.0, &file.0, chunk, ChunkPosition::default(), &options).is_err());
+    assert_eq!(fs::read(&file.0).unwrap(), before);
+  }
+
+  #[test]
+  fn test_discard_unsafe() {
+    let chunks: [(&str, &[u8]); 5] = [
+      ("IHDR", &[0; 13]),
+      ("gAMA", &[0; 4]),
+      ("ruST", b"unsafe"),
+      ("ruSt", b"safe"),
+      ("IEND", b""),
+    ];
+    let file = TestingFile::new("discard-unsafe", &chunks);
+    let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hello".to_vec());
+    let options = WriteOptions {
+      keep_unsafe: true,
+      ..Default::default()
+    };
+    let (_, discarded) = encode(&file.0, &file.0, chunk.clone(), ChunkPosition::default(), &options).unwrap();
+    assert!(discarded.is_empty());
+    let (removed, discarded) = remove(&file.0, "RuSt", &WriteOptions::default()).unwrap();
+    assert_eq!(removed.data(), b"hello");
+    let discarded: Vec<String> = discarded.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+    assert_eq!(discarded, ["ruST"]);
+    assert!(!has_chunk(&file.0, "ruST"));
+    assert!(has_chunk(&file.0, "gAMA"));
+    assert!(remove(&file.0, "RuSt", &WriteOptions::default()).is_err());
+    let (_, discarded) = encode(&file.0, &file.0, chunk, ChunkPosition::default(), &WriteOptions::default()).unwrap();
+    assert!(discarded.is_empty());
+  }
+}
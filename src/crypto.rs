@@ -0,0 +1,482 @@
+//! Authenticated encryption of chunk payloads
+//!
+//! A sealed payload is a versioned header followed by the ciphertext. All integers are big endian.
+//! Every payload starts with the same prefix:
+//!
+//! | field      | size | value                                   |
+//! |------------|------|-----------------------------------------|
+//! | magic      | 4    | `PMsl`                                  |
+//! | version    | 1    | `1`                                     |
+//! | algorithm  | 1    | `1` XChaCha20-Poly1305                  |
+//! | kdf        | 1    | `1` Argon2id, `2` X25519 recipients     |
+//!
+//! For a passphrase (`kdf` 1) the prefix is followed by:
+//!
+//! | field      | size | value                                   |
+//! |------------|------|-----------------------------------------|
+//! | m_cost     | 4    | Argon2 memory cost in KiB               |
+//! | t_cost     | 4    | Argon2 iterations                       |
+//! | p_cost     | 4    | Argon2 parallelism                      |
+//! | salt       | 16   | random Argon2 salt                      |
+//!
+//! For recipients (`kdf` 2) the payload is encrypted with a random file key, wrapped for every
+//! recipient with XChaCha20-Poly1305 under a zero nonce and a key derived with HKDF-SHA256 from
+//! the X25519 shared secret, the `salt` being the ephemeral then the recipient public key and the
+//! `info` being `pngme-x25519-v1`. The prefix is followed by:
+//!
+//! | field      | size | value                                   |
+//! |------------|------|-----------------------------------------|
+//! | ephemeral  | 32   | ephemeral X25519 public key             |
+//! | count      | 1    | number of recipients, at least 1        |
+//! | recipients | 80 * count | recipient public key (32) and wrapped file key (48) |
+//!
+//! Both end with:
+//!
+//! | field      | size | value                                   |
+//! |------------|------|-----------------------------------------|
+//! | nonce      | 24   | random XChaCha20 nonce                  |
+//! | ciphertext | rest | encrypted payload and 16 byte auth tag  |
+//!
+//! The whole header is authenticated as associated data, so tampering with any byte is rejected.
+//!
+//! Key files hold one hex encoded 32 byte key per file, lines starting with `#` being comments.
+
+use std::{error, fmt, result};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+pub use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Marks the start of a sealed payload
+pub const MAGIC: [u8; 4] = *b"PMsl";
+pub const VERSION: u8 = 1;
+const ALGORITHM_XCHACHA20_POLY1305: u8 = 1;
+const KDF_ARGON2ID: u8 = 1;
+const KDF_X25519: u8 = 2;
+const PREFIX_LEN: usize = MAGIC.len() + 3;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const PASSPHRASE_HEADER_LEN: usize = PREFIX_LEN + 12 + SALT_LEN + NONCE_LEN;
+const RECIPIENT_LEN: usize = KEY_LEN + KEY_LEN + TAG_LEN;
+const WRAP_INFO: &[u8] = b"pngme-x25519-v1";
+/// Largest Argon2 memory cost accepted when opening, 1 GiB
+const MAX_M_COST: u32 = 1 << 20;
+/// Largest Argon2 iteration count accepted when opening
+const MAX_T_COST: u32 = 16;
+/// Largest Argon2 parallelism accepted when opening
+const MAX_P_COST: u32 = 16;
+
+/// Crypto errors
+#[derive(Debug)]
+pub enum CryptoError {
+  /// The data does not start with the sealed payload magic
+  NotSealed,
+  /// The header is shorter than expected
+  Truncated(usize, usize),
+  UnsupportedVersion(u8),
+  UnsupportedAlgorithm(u8),
+  UnsupportedKdf(u8),
+  /// The key derivation parameters are invalid or too expensive
+  InvalidKdfParams,
+  /// Wrong passphrase or tampered data
+  Decryption,
+  /// A key file does not hold a hex encoded 32 byte key
+  InvalidKey,
+  /// Between 1 and 255 recipients are supported
+  InvalidRecipientCount(usize),
+  /// None of the identities is a recipient of the payload
+  NoMatchingIdentity,
+}
+
+impl error::Error for CryptoError {}
+
+impl fmt::Display for CryptoError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CryptoError::NotSealed => write!(f, "The data is not an encrypted payload"),
+      CryptoError::Truncated(expected, actual) => write!(
+        f,
+        "Expected at least {} bytes of encrypted payload but found {}",
+        expected, actual
+      ),
+      CryptoError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported encrypted payload version {}", version)
+      }
+      CryptoError::UnsupportedAlgorithm(algorithm) => {
+        write!(f, "Unsupported encryption algorithm {}", algorithm)
+      }
+      CryptoError::UnsupportedKdf(kdf) => write!(f, "Unsupported key derivation function {}", kdf),
+      CryptoError::InvalidKdfParams => write!(f, "Invalid key derivation parameters"),
+      CryptoError::Decryption => write!(f, "Wrong passphrase or tampered data"),
+      CryptoError::InvalidKey => write!(f, "Expected a hex encoded 32 byte key"),
+      CryptoError::InvalidRecipientCount(count) => {
+        write!(f, "Expected between 1 and 255 recipients but found {}", count)
+      }
+      CryptoError::NoMatchingIdentity => {
+        write!(f, "None of the identities is a recipient of the message")
+      }
+    }
+  }
+}
+
+/// How the key of a sealed payload is obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+  Passphrase,
+  Recipients,
+}
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+  /// Memory cost in KiB
+  pub m_cost: u32,
+  pub t_cost: u32,
+  pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+  fn default() -> KdfParams {
+    KdfParams {
+      m_cost: Params::DEFAULT_M_COST,
+      t_cost: Params::DEFAULT_T_COST,
+      p_cost: Params::DEFAULT_P_COST,
+    }
+  }
+}
+
+impl KdfParams {
+  fn derive_key(&self, passphrase: &[u8], salt: &[u8]) -> result::Result<[u8; KEY_LEN], CryptoError> {
+    if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+      return Err(CryptoError::InvalidKdfParams);
+    }
+    let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN))
+      .map_err(|_| CryptoError::InvalidKdfParams)?;
+    let mut key = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+      .hash_password_into(passphrase, salt, &mut key)
+      .map_err(|_| CryptoError::InvalidKdfParams)?;
+    Ok(key)
+  }
+}
+
+/// Whether the data starts like a sealed payload
+pub fn is_sealed(data: &[u8]) -> bool {
+  data.starts_with(&MAGIC)
+}
+
+/// Checks the common prefix of a sealed payload and returns how its key is obtained
+pub fn scheme(sealed: &[u8]) -> result::Result<Scheme, CryptoError> {
+  if !is_sealed(sealed) {
+    return Err(CryptoError::NotSealed);
+  }
+  if sealed.len() < PREFIX_LEN {
+    return Err(CryptoError::Truncated(PREFIX_LEN, sealed.len()));
+  }
+  match sealed[4] {
+    VERSION => {}
+    version => return Err(CryptoError::UnsupportedVersion(version)),
+  }
+  match sealed[5] {
+    ALGORITHM_XCHACHA20_POLY1305 => {}
+    algorithm => return Err(CryptoError::UnsupportedAlgorithm(algorithm)),
+  }
+  match sealed[6] {
+    KDF_ARGON2ID => Ok(Scheme::Passphrase),
+    KDF_X25519 => Ok(Scheme::Recipients),
+    kdf => Err(CryptoError::UnsupportedKdf(kdf)),
+  }
+}
+
+fn prefix(kdf: u8) -> Vec<u8> {
+  MAGIC
+    .iter()
+    .chain([VERSION, ALGORITHM_XCHACHA20_POLY1305, kdf].iter())
+    .copied()
+    .collect()
+}
+
+/// Appends the nonce and encrypts the payload, authenticating the whole header
+fn encrypt(mut header: Vec<u8>, key: &[u8; KEY_LEN], plaintext: &[u8]) -> result::Result<Vec<u8>, CryptoError> {
+  let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+  header.extend(nonce.iter());
+  let ciphertext = XChaCha20Poly1305::new(key.into())
+    .encrypt(&nonce, Payload { msg: plaintext, aad: &header })
+    .map_err(|_| CryptoError::Decryption)?;
+  header.extend(ciphertext);
+  Ok(header)
+}
+
+/// Decrypts the payload whose header, nonce included, is `header_len` bytes long
+fn decrypt(sealed: &[u8], header_len: usize, key: &[u8; KEY_LEN]) -> result::Result<Vec<u8>, CryptoError> {
+  let (header, ciphertext) = sealed.split_at(header_len);
+  let nonce = XNonce::from_slice(&header[header_len - NONCE_LEN..]);
+  XChaCha20Poly1305::new(key.into())
+    .decrypt(nonce, Payload { msg: ciphertext, aad: header })
+    .map_err(|_| CryptoError::Decryption)
+}
+
+fn check_len(sealed: &[u8], expected: usize) -> result::Result<(), CryptoError> {
+  match sealed.len() < expected {
+    true => Err(CryptoError::Truncated(expected, sealed.len())),
+    false => Ok(()),
+  }
+}
+
+/// Encrypts the payload with a key derived from the passphrase using the default parameters
+pub fn seal(plaintext: &[u8], passphrase: &[u8]) -> result::Result<Vec<u8>, CryptoError> {
+  seal_with(plaintext, passphrase, KdfParams::default())
+}
+
+/// Encrypts the payload with a key derived from the passphrase
+pub fn seal_with(
+  plaintext: &[u8],
+  passphrase: &[u8],
+  params: KdfParams,
+) -> result::Result<Vec<u8>, CryptoError> {
+  let mut salt = [0u8; SALT_LEN];
+  OsRng.fill_bytes(&mut salt);
+  let key = params.derive_key(passphrase, &salt)?;
+  let mut header = prefix(KDF_ARGON2ID);
+  header.extend(params.m_cost.to_be_bytes());
+  header.extend(params.t_cost.to_be_bytes());
+  header.extend(params.p_cost.to_be_bytes());
+  header.extend(salt);
+  encrypt(header, &key, plaintext)
+}
+
+/// Decrypts a passphrase sealed payload, rejecting a wrong passphrase or tampered data
+pub fn open(sealed: &[u8], passphrase: &[u8]) -> result::Result<Vec<u8>, CryptoError> {
+  if scheme(sealed)? != Scheme::Passphrase {
+    return Err(CryptoError::UnsupportedKdf(sealed[6]));
+  }
+  check_len(sealed, PASSPHRASE_HEADER_LEN)?;
+  let read_u32 = |offset: usize| {
+    u32::from_be_bytes([sealed[offset], sealed[offset + 1], sealed[offset + 2], sealed[offset + 3]])
+  };
+  let params = KdfParams {
+    m_cost: read_u32(PREFIX_LEN),
+    t_cost: read_u32(PREFIX_LEN + 4),
+    p_cost: read_u32(PREFIX_LEN + 8),
+  };
+  let salt = &sealed[PREFIX_LEN + 12..PREFIX_LEN + 12 + SALT_LEN];
+  let key = params.derive_key(passphrase, salt)?;
+  decrypt(sealed, PASSPHRASE_HEADER_LEN, &key)
+}
+
+/// Key wrapping the file key for one recipient
+fn wrap_key(shared_secret: &[u8; KEY_LEN], ephemeral: &PublicKey, recipient: &PublicKey) -> [u8; KEY_LEN] {
+  let salt = [ephemeral.as_bytes().as_slice(), recipient.as_bytes().as_slice()].concat();
+  let mut key = [0u8; KEY_LEN];
+  Hkdf::<Sha256>::new(Some(&salt), shared_secret)
+    .expand(WRAP_INFO, &mut key)
+    .expect("32 bytes is a valid HKDF-SHA256 output length");
+  key
+}
+
+/// X25519 shared secret, rejecting low order public keys
+fn shared_secret(secret: &StaticSecret, public: &PublicKey) -> result::Result<[u8; KEY_LEN], CryptoError> {
+  let shared_secret = secret.diffie_hellman(public);
+  match shared_secret.was_contributory() {
+    true => Ok(shared_secret.to_bytes()),
+    false => Err(CryptoError::InvalidKey),
+  }
+}
+
+/// Encrypts the payload so that any of the recipients can open it
+pub fn seal_for(plaintext: &[u8], recipients: &[PublicKey]) -> result::Result<Vec<u8>, CryptoError> {
+  let count = u8::try_from(recipients.len())
+    .ok()
+    .filter(|&count| count > 0)
+    .ok_or(CryptoError::InvalidRecipientCount(recipients.len()))?;
+  let mut file_key = [0u8; KEY_LEN];
+  OsRng.fill_bytes(&mut file_key);
+  let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
+  let ephemeral = PublicKey::from(&ephemeral_secret);
+
+  let mut header = prefix(KDF_X25519);
+  header.extend(ephemeral.as_bytes());
+  header.push(count);
+  for recipient in recipients {
+    let key = wrap_key(&shared_secret(&ephemeral_secret, recipient)?, &ephemeral, recipient);
+    let wrapped = XChaCha20Poly1305::new(&key.into())
+      .encrypt(&XNonce::default(), file_key.as_slice())
+      .map_err(|_| CryptoError::Decryption)?;
+    header.extend(recipient.as_bytes());
+    header.extend(wrapped);
+  }
+  encrypt(header, &file_key, plaintext)
+}
+
+/// Decrypts a payload sealed for recipients with the secret key of any of them
+pub fn open_for(sealed: &[u8], identities: &[StaticSecret]) -> result::Result<Vec<u8>, CryptoError> {
+  if scheme(sealed)? != Scheme::Recipients {
+    return Err(CryptoError::UnsupportedKdf(sealed[6]));
+  }
+  check_len(sealed, PREFIX_LEN + KEY_LEN + 1)?;
+  let ephemeral = PublicKey::from(read_key(&sealed[PREFIX_LEN..]));
+  let count = sealed[PREFIX_LEN + KEY_LEN] as usize;
+  let recipients_start = PREFIX_LEN + KEY_LEN + 1;
+  let header_len = recipients_start + count * RECIPIENT_LEN + NONCE_LEN;
+  check_len(sealed, header_len)?;
+
+  for recipient in sealed[recipients_start..header_len - NONCE_LEN].chunks_exact(RECIPIENT_LEN) {
+    let recipient_key = PublicKey::from(read_key(recipient));
+    let Some(identity) = identities
+      .iter()
+      .find(|identity| PublicKey::from(*identity) == recipient_key)
+    else {
+      continue;
+    };
+    let key = wrap_key(&shared_secret(identity, &ephemeral)?, &ephemeral, &recipient_key);
+    let file_key: [u8; KEY_LEN] = XChaCha20Poly1305::new(&key.into())
+      .decrypt(&XNonce::default(), &recipient[KEY_LEN..])
+      .map_err(|_| CryptoError::Decryption)?
+      .try_into()
+      .map_err(|_| CryptoError::Decryption)?;
+    return decrypt(sealed, header_len, &file_key);
+  }
+  Err(CryptoError::NoMatchingIdentity)
+}
+
+fn read_key(bytes: &[u8]) -> [u8; KEY_LEN] {
+  let mut key = [0u8; KEY_LEN];
+  key.copy_from_slice(&bytes[..KEY_LEN]);
+  key
+}
+
+/// Generates a new secret key
+pub fn generate_identity() -> StaticSecret {
+  StaticSecret::random_from_rng(OsRng)
+}
+
+/// Parses the content of a key file
+fn parse_key(text: &str) -> result::Result<[u8; KEY_LEN], CryptoError> {
+  let hex = text
+    .lines()
+    .map(str::trim)
+    .find(|line| !line.is_empty() && !line.starts_with('#'))
+    .ok_or(CryptoError::InvalidKey)?;
+  if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+    return Err(CryptoError::InvalidKey);
+  }
+  let mut key = [0u8; KEY_LEN];
+  for (idx, byte) in key.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).map_err(|_| CryptoError::InvalidKey)?;
+  }
+  Ok(key)
+}
+
+fn format_key(comment: &str, key: &[u8; KEY_LEN]) -> String {
+  let hex: String = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+  format!("# {}\n{}\n", comment, hex)
+}
+
+/// Parses the content of a public key file
+pub fn parse_public_key(text: &str) -> result::Result<PublicKey, CryptoError> {
+  parse_key(text).map(PublicKey::from)
+}
+
+/// Parses the content of an identity file
+pub fn parse_identity(text: &str) -> result::Result<StaticSecret, CryptoError> {
+  parse_key(text).map(StaticSecret::from)
+}
+
+/// Content of a public key file
+pub fn format_public_key(public_key: &PublicKey) -> String {
+  format_key("pngme public key", public_key.as_bytes())
+}
+
+/// Content of an identity file
+pub fn format_identity(identity: &StaticSecret) -> String {
+  format_key("pngme identity, keep it secret", identity.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Cheap parameters so that tests stay fast
+  const TEST_PARAMS: KdfParams = KdfParams {
+    m_cost: 64,
+    t_cost: 1,
+    p_cost: 1,
+  };
+
+  #[test]
+  fn test_seal_and_open() {
+    let sealed = seal_with(b"secret message", b"passphrase", TEST_PARAMS).unwrap();
+    assert!(is_sealed(&sealed));
+    assert_eq!(scheme(&sealed).unwrap(), Scheme::Passphrase);
+    assert_eq!(sealed.len(), PASSPHRASE_HEADER_LEN + 14 + TAG_LEN);
+    assert_eq!(open(&sealed, b"passphrase").unwrap(), b"secret message");
+  }
+
+  #[test]
+  fn test_open_wrong_passphrase() {
+    let sealed = seal_with(b"secret message", b"passphrase", TEST_PARAMS).unwrap();
+    assert!(matches!(open(&sealed, b"wrong"), Err(CryptoError::Decryption)));
+  }
+
+  #[test]
+  fn test_open_tampered() {
+    let sealed = seal_with(b"secret message", b"passphrase", TEST_PARAMS).unwrap();
+    for idx in [19, PASSPHRASE_HEADER_LEN - 1, sealed.len() - 1] {
+      let mut tampered = sealed.clone();
+      tampered[idx] ^= 1;
+      assert!(matches!(open(&tampered, b"passphrase"), Err(CryptoError::Decryption)));
+    }
+  }
+
+  #[test]
+  fn test_open_invalid_header() {
+    let sealed = seal_with(b"secret message", b"passphrase", TEST_PARAMS).unwrap();
+    assert!(matches!(open(b"secret", b"passphrase"), Err(CryptoError::NotSealed)));
+    assert!(matches!(open(&sealed[..20], b"passphrase"), Err(CryptoError::Truncated(_, 20))));
+    let mut tampered = sealed.clone();
+    tampered[4] = 9;
+    assert!(matches!(open(&tampered, b"passphrase"), Err(CryptoError::UnsupportedVersion(9))));
+    for range in [7..11, 11..15, 15..19] {
+      let mut tampered = sealed.clone();
+      tampered[range].copy_from_slice(&u32::MAX.to_be_bytes());
+      assert!(matches!(open(&tampered, b"passphrase"), Err(CryptoError::InvalidKdfParams)));
+    }
+  }
+
+  #[test]
+  fn test_seal_for_recipients() {
+    let alice = generate_identity();
+    let bob = generate_identity();
+    let eve = generate_identity();
+    let recipients = [PublicKey::from(&alice), PublicKey::from(&bob)];
+    let sealed = seal_for(b"secret message", &recipients).unwrap();
+    assert_eq!(scheme(&sealed).unwrap(), Scheme::Recipients);
+    assert_eq!(open_for(&sealed, std::slice::from_ref(&bob)).unwrap(), b"secret message");
+    assert_eq!(open_for(&sealed, &[eve.clone(), alice]).unwrap(), b"secret message");
+    assert!(matches!(open_for(&sealed, &[eve]), Err(CryptoError::NoMatchingIdentity)));
+
+    let mut tampered = sealed.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    assert!(matches!(open_for(&tampered, std::slice::from_ref(&bob)), Err(CryptoError::Decryption)));
+    assert!(matches!(open_for(&sealed[..50], &[bob]), Err(CryptoError::Truncated(_, 50))));
+    assert!(matches!(seal_for(b"secret", &[]), Err(CryptoError::InvalidRecipientCount(0))));
+  }
+
+  #[test]
+  fn test_key_files() {
+    let identity = generate_identity();
+    let public_key = PublicKey::from(&identity);
+    let parsed = parse_identity(&format_identity(&identity)).unwrap();
+    assert_eq!(parsed.to_bytes(), identity.to_bytes());
+    assert_eq!(parse_public_key(&format_public_key(&public_key)).unwrap(), public_key);
+    assert!(parse_public_key("# comment\nabcd").is_err());
+    assert!(parse_public_key(&"zz".repeat(32)).is_err());
+  }
+}
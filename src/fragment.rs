@@ -0,0 +1,301 @@
+//! Payloads split across several chunks
+//!
+//! Every fragment is a header followed by a slice of the payload. All integers are big endian:
+//!
+//! | field   | size | value                                          |
+//! |---------|------|------------------------------------------------|
+//! | magic   | 4    | `PMfr`                                         |
+//! | version | 1    | `1`                                            |
+//! | id      | 4    | crc of the whole payload, shared by fragments  |
+//! | index   | 4    | position of the fragment, starting at 0        |
+//! | count   | 4    | number of fragments                            |
+//! | data    | rest | slice of the payload                           |
+
+use std::{error, fmt, result};
+
+use crate::chunk::{Chunk, CRC};
+
+/// Marks the start of a fragment
+pub const MAGIC: [u8; 4] = *b"PMfr";
+pub const VERSION: u8 = 1;
+/// Size of the fragment header
+pub const HEADER_LEN: usize = MAGIC.len() + 1 + 12;
+
+/// Fragment errors
+#[derive(Debug)]
+pub enum FragmentError {
+  /// The data does not start with the fragment magic
+  NotFragment,
+  UnsupportedVersion(u8),
+  /// The fragment is shorter than its header
+  Truncated(usize),
+  /// The index is not smaller than the count
+  InvalidIndex(u32, u32),
+  /// Fragments of the same payload disagree on the number of fragments
+  InconsistentCount(u32, u32),
+  /// Indices of missing and duplicated fragments
+  Incomplete(Vec<u32>, Vec<u32>),
+  /// The count is larger than the number of fragments present
+  TooFewFragments(u32, usize),
+  /// The reassembled payload does not match the crc in the fragment id
+  ChecksumMismatch(u32, u32),
+  /// The maximum chunk size leaves no room for data or yields too many fragments
+  InvalidMaxLength(usize),
+}
+
+impl error::Error for FragmentError {}
+
+impl fmt::Display for FragmentError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FragmentError::NotFragment => write!(f, "The data is not a fragment"),
+      FragmentError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported fragment version {}", version)
+      }
+      FragmentError::Truncated(actual) => write!(
+        f,
+        "Expected at least {} bytes of fragment but found {}",
+        HEADER_LEN, actual
+      ),
+      FragmentError::InvalidIndex(index, count) => {
+        write!(f, "Fragment index {} is out of range for {} fragments", index, count)
+      }
+      FragmentError::InconsistentCount(expected, actual) => write!(
+        f,
+        "Inconsistent fragment count. Expected {} but found {}",
+        expected, actual
+      ),
+      FragmentError::Incomplete(missing, duplicated) => write!(
+        f,
+        "Incomplete fragments, missing {:?} and duplicated {:?}",
+        missing, duplicated
+      ),
+      FragmentError::TooFewFragments(count, present) => write!(
+        f,
+        "Expected {} fragments but only {} are present",
+        count, present
+      ),
+      FragmentError::ChecksumMismatch(expected, actual) => write!(
+        f,
+        "Invalid reassembled payload crc. Expected {} but found {}",
+        expected, actual
+      ),
+      FragmentError::InvalidMaxLength(max_len) => {
+        write!(f, "Cannot split the payload into chunks of at most {} bytes", max_len)
+      }
+    }
+  }
+}
+
+/// A slice of a payload parsed from chunk data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment<'a> {
+  pub id: u32,
+  pub index: u32,
+  pub count: u32,
+  pub data: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+  /// Whether the data starts like a fragment
+  pub fn is_fragment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+  }
+
+  fn to_bytes(self) -> Vec<u8> {
+    MAGIC
+      .iter()
+      .chain([VERSION].iter())
+      .chain(self.id.to_be_bytes().iter())
+      .chain(self.index.to_be_bytes().iter())
+      .chain(self.count.to_be_bytes().iter())
+      .chain(self.data.iter())
+      .copied()
+      .collect()
+  }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Fragment<'a> {
+  type Error = FragmentError;
+  fn try_from(bytes: &'a [u8]) -> result::Result<Fragment<'a>, FragmentError> {
+    if !Fragment::is_fragment(bytes) {
+      return Err(FragmentError::NotFragment);
+    }
+    if bytes.len() < HEADER_LEN {
+      return Err(FragmentError::Truncated(bytes.len()));
+    }
+    if bytes[4] != VERSION {
+      return Err(FragmentError::UnsupportedVersion(bytes[4]));
+    }
+    let read_u32 = |offset: usize| {
+      u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    };
+    let fragment = Fragment {
+      id: read_u32(5),
+      index: read_u32(9),
+      count: read_u32(13),
+      data: &bytes[HEADER_LEN..],
+    };
+    if fragment.index >= fragment.count {
+      return Err(FragmentError::InvalidIndex(fragment.index, fragment.count));
+    }
+    Ok(fragment)
+  }
+}
+
+/// Splits a payload into fragments whose chunk data is at most `max_len` bytes
+pub fn split(payload: &[u8], max_len: usize) -> result::Result<Vec<Vec<u8>>, FragmentError> {
+  let max_len = max_len.min(Chunk::MAX_LENGTH as usize);
+  if max_len <= HEADER_LEN {
+    return Err(FragmentError::InvalidMaxLength(max_len));
+  }
+  let slices: Vec<&[u8]> = match payload.is_empty() {
+    true => vec![payload],
+    false => payload.chunks(max_len - HEADER_LEN).collect(),
+  };
+  let count = u32::try_from(slices.len()).map_err(|_| FragmentError::InvalidMaxLength(max_len))?;
+  let id = CRC.checksum(payload);
+  let fragments = slices
+    .into_iter()
+    .zip(0..)
+    .map(|(data, index)| Fragment { id, index, count, data }.to_bytes())
+    .collect();
+  Ok(fragments)
+}
+
+/// Reassembles the payload of the first fragment from fragments in any order
+///
+/// Fragments of other payloads are ignored.
+pub fn reassemble<'a, I>(fragments: I) -> result::Result<Vec<u8>, FragmentError>
+where
+  I: IntoIterator<Item = &'a [u8]>,
+{
+  let fragments = fragments
+    .into_iter()
+    .map(Fragment::try_from)
+    .collect::<result::Result<Vec<_>, _>>()?;
+  let first = fragments.first().ok_or(FragmentError::NotFragment)?;
+  let present = fragments.iter().filter(|fragment| fragment.id == first.id).count();
+  // the count comes from the file, so it is checked before sizing anything by it
+  if first.count as usize > present {
+    return Err(FragmentError::TooFewFragments(first.count, present));
+  }
+  let mut slots: Vec<Option<&[u8]>> = vec![None; first.count as usize];
+  let mut duplicated = Vec::new();
+  for fragment in &fragments {
+    if fragment.id != first.id {
+      continue;
+    }
+    if fragment.count != first.count {
+      return Err(FragmentError::InconsistentCount(first.count, fragment.count));
+    }
+    let slot = &mut slots[fragment.index as usize];
+    match slot {
+      Some(_) => duplicated.push(fragment.index),
+      None => *slot = Some(fragment.data),
+    }
+  }
+  let missing: Vec<u32> = (0..first.count)
+    .filter(|&index| slots[index as usize].is_none())
+    .collect();
+  if !missing.is_empty() || !duplicated.is_empty() {
+    return Err(FragmentError::Incomplete(missing, duplicated));
+  }
+  let payload: Vec<u8> = slots.into_iter().flatten().flatten().copied().collect();
+  let crc = CRC.checksum(&payload);
+  if crc != first.id {
+    return Err(FragmentError::ChecksumMismatch(first.id, crc));
+  }
+  Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn testing_payload() -> Vec<u8> {
+    (0..100u8).collect()
+  }
+
+  #[test]
+  fn test_split_and_reassemble() {
+    let payload = testing_payload();
+    let fragments = split(&payload, HEADER_LEN + 30).unwrap();
+    assert_eq!(fragments.len(), 4);
+    assert!(fragments.iter().all(|fragment| fragment.len() <= HEADER_LEN + 30));
+    let reassembled = reassemble(fragments.iter().rev().map(Vec::as_slice)).unwrap();
+    assert_eq!(reassembled, payload);
+  }
+
+  #[test]
+  fn test_split_empty_payload() {
+    let fragments = split(&[], 100).unwrap();
+    assert_eq!(fragments.len(), 1);
+    assert!(reassemble(fragments.iter().map(Vec::as_slice)).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_split_invalid_max_len() {
+    assert!(matches!(
+      split(&testing_payload(), HEADER_LEN),
+      Err(FragmentError::InvalidMaxLength(_))
+    ));
+  }
+
+  #[test]
+  fn test_reassemble_missing_and_duplicated() {
+    let fragments = split(&testing_payload(), HEADER_LEN + 30).unwrap();
+    let incomplete = [&fragments[0], &fragments[2], &fragments[2], &fragments[3]];
+    match reassemble(incomplete.iter().map(|fragment| fragment.as_slice())) {
+      Err(FragmentError::Incomplete(missing, duplicated)) => {
+        assert_eq!(missing, [1]);
+        assert_eq!(duplicated, [2]);
+      }
+      _ => panic!("expected incomplete fragments"),
+    }
+    assert!(matches!(
+      reassemble(incomplete[..3].iter().map(|fragment| fragment.as_slice())),
+      Err(FragmentError::TooFewFragments(4, 3))
+    ));
+  }
+
+  #[test]
+  fn test_reassemble_huge_count() {
+    let fragment = Fragment {
+      id: 0,
+      index: 0,
+      count: u32::MAX,
+      data: b"data",
+    }
+    .to_bytes();
+    assert!(matches!(
+      reassemble([fragment.as_slice()]),
+      Err(FragmentError::TooFewFragments(u32::MAX, 1))
+    ));
+  }
+
+  #[test]
+  fn test_reassemble_ignores_other_payloads() {
+    let fragments = split(&testing_payload(), HEADER_LEN + 60).unwrap();
+    let others = split(b"another payload", HEADER_LEN + 60).unwrap();
+    let mixed = [&fragments[1], &others[0], &fragments[0]];
+    let reassembled = reassemble(mixed.iter().map(|fragment| fragment.as_slice())).unwrap();
+    assert_eq!(reassembled, testing_payload());
+  }
+
+  #[test]
+  fn test_reassemble_tampered() {
+    let mut fragments = split(&testing_payload(), HEADER_LEN + 60).unwrap();
+    let last = fragments[1].len() - 1;
+    fragments[1][last] ^= 1;
+    assert!(matches!(
+      reassemble(fragments.iter().map(Vec::as_slice)),
+      Err(FragmentError::ChecksumMismatch(_, _))
+    ));
+    fragments[1][9..13].copy_from_slice(&7u32.to_be_bytes());
+    assert!(matches!(
+      reassemble(fragments.iter().map(Vec::as_slice)),
+      Err(FragmentError::InvalidIndex(7, 2))
+    ));
+  }
+}
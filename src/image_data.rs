@@ -0,0 +1,349 @@
+//! The zlib stream of the IDAT chunks and its scanline filters
+//!
+//! Raw image data is the concatenation of the unfiltered scanlines without their filter type
+//! byte. Interlaced images store the scanlines of the seven Adam7 passes one pass after another.
+
+use std::io::{self, Read, Write};
+use std::{error, fmt, result};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::image_header::{ColorType, ImageHeader, InterlaceMethod};
+
+/// Origin and spacing of the pixels of each Adam7 pass
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+  (0, 0, 8, 8),
+  (4, 0, 8, 8),
+  (0, 4, 4, 8),
+  (2, 0, 4, 4),
+  (0, 2, 2, 4),
+  (1, 0, 2, 2),
+  (0, 1, 1, 2),
+];
+
+/// How raw image data is stored back into IDAT chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageDataOptions {
+  /// Maximum number of bytes in each IDAT chunk
+  pub chunk_size: usize,
+  /// Zlib compression level, from 0 (none) to 9 (best)
+  pub compression_level: u32,
+}
+
+impl Default for ImageDataOptions {
+  fn default() -> Self {
+    ImageDataOptions {
+      chunk_size: 8192,
+      compression_level: 6,
+    }
+  }
+}
+
+/// Image data errors
+#[derive(Debug)]
+pub enum ImageDataError {
+  /// The IDAT chunks are not a valid zlib stream
+  Inflate(io::Error),
+  /// The image data does not have the size described by IHDR
+  InvalidLength(usize, usize),
+  /// A scanline starts with an unknown filter type
+  InvalidFilterType(u8),
+  /// The dimensions in IHDR describe more data than can be addressed
+  TooLarge,
+  /// The IDAT chunk size is zero or larger than a chunk can hold
+  InvalidChunkSize(usize),
+}
+
+impl error::Error for ImageDataError {}
+
+impl fmt::Display for ImageDataError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ImageDataError::Inflate(error) => write!(f, "Invalid compressed image data: {}", error),
+      ImageDataError::InvalidLength(expected, actual) => write!(
+        f,
+        "Invalid image data length. Expected {} bytes but found {}",
+        expected, actual
+      ),
+      ImageDataError::InvalidFilterType(filter_type) => {
+        write!(f, "Invalid scanline filter type {}", filter_type)
+      }
+      ImageDataError::TooLarge => write!(f, "The image is too large"),
+      ImageDataError::InvalidChunkSize(size) => write!(f, "Invalid IDAT chunk size {}", size),
+    }
+  }
+}
+
+/// Number of samples per pixel
+pub fn channels(color_type: ColorType) -> usize {
+  match color_type {
+    ColorType::Grayscale | ColorType::Indexed => 1,
+    ColorType::GrayscaleAlpha => 2,
+    ColorType::Rgb => 3,
+    ColorType::Rgba => 4,
+  }
+}
+
+/// Width and height of every non empty pass, a single one unless the image is interlaced
+fn passes(header: &ImageHeader) -> Vec<(usize, usize)> {
+  let (width, height) = (header.width, header.height);
+  match header.interlace_method {
+    InterlaceMethod::None => vec![(width as usize, height as usize)],
+    InterlaceMethod::Adam7 => ADAM7
+      .iter()
+      .map(|&(x0, y0, dx, dy)| {
+        let len = |size: u32, origin: u32, step: u32| size.saturating_sub(origin).div_ceil(step) as usize;
+        (len(width, x0, dx), len(height, y0, dy))
+      })
+      .filter(|&(width, height)| width > 0 && height > 0)
+      .collect(),
+  }
+}
+
+/// Bytes per complete pixel, rounded up to one for bit depths below 8
+fn filter_bpp(header: &ImageHeader) -> usize {
+  (channels(header.color_type) * header.bit_depth as usize).div_ceil(8)
+}
+
+/// Bytes in a scanline of the given width, without the filter type byte
+fn row_len(header: &ImageHeader, width: usize) -> Option<usize> {
+  let bits = width.checked_mul(channels(header.color_type) * header.bit_depth as usize)?;
+  Some(bits.div_ceil(8))
+}
+
+/// Scanline length and count of every pass
+fn rows(header: &ImageHeader) -> result::Result<Vec<(usize, usize)>, ImageDataError> {
+  passes(header)
+    .into_iter()
+    .map(|(width, height)| Ok((row_len(header, width).ok_or(ImageDataError::TooLarge)?, height)))
+    .collect()
+}
+
+/// Size of the raw image data described by the header
+pub fn raw_len(header: &ImageHeader) -> result::Result<usize, ImageDataError> {
+  rows(header)?.iter().try_fold(0usize, |total, &(row_len, height)| {
+    total
+      .checked_add(row_len.checked_mul(height)?)
+      .ok_or(ImageDataError::TooLarge)
+      .ok()
+  })
+  .ok_or(ImageDataError::TooLarge)
+}
+
+/// Decompresses the concatenated data of the IDAT chunks
+pub fn inflate(compressed: &[u8], expected_len: usize) -> result::Result<Vec<u8>, ImageDataError> {
+  let mut inflated = Vec::new();
+  ZlibDecoder::new(compressed)
+    .take(expected_len as u64 + 1)
+    .read_to_end(&mut inflated)
+    .map_err(ImageDataError::Inflate)?;
+  Ok(inflated)
+}
+
+/// Compresses filtered image data, `level` going from 0 (none) to 9 (best)
+pub fn deflate(filtered: &[u8], level: u32) -> Vec<u8> {
+  let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+  encoder
+    .write_all(filtered)
+    .and_then(|_| encoder.finish())
+    .expect("writing to a Vec does not fail")
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+  let p = left as i16 + up as i16 - up_left as i16;
+  let (pa, pb, pc) = ((p - left as i16).abs(), (p - up as i16).abs(), (p - up_left as i16).abs());
+  if pa <= pb && pa <= pc {
+    left
+  } else if pb <= pc {
+    up
+  } else {
+    up_left
+  }
+}
+
+/// Predictor of a byte for the given filter type
+fn predict(filter_type: u8, left: u8, up: u8, up_left: u8) -> u8 {
+  match filter_type {
+    1 => left,
+    2 => up,
+    3 => ((left as u16 + up as u16) / 2) as u8,
+    4 => paeth(left, up, up_left),
+    _ => 0,
+  }
+}
+
+/// Removes the scanline filters of decompressed image data
+pub fn unfilter(header: &ImageHeader, filtered: &[u8]) -> result::Result<Vec<u8>, ImageDataError> {
+  let rows = rows(header)?;
+  let expected_len = rows
+    .iter()
+    .try_fold(0usize, |total, &(row_len, height)| total.checked_add((row_len + 1).checked_mul(height)?))
+    .ok_or(ImageDataError::TooLarge)?;
+  if filtered.len() != expected_len {
+    return Err(ImageDataError::InvalidLength(expected_len, filtered.len()));
+  }
+  let bpp = filter_bpp(header);
+  let mut raw = Vec::with_capacity(raw_len(header)?);
+  let mut lines = filtered;
+  for (row_len, height) in rows {
+    let pass_start = raw.len();
+    for row in 0..height {
+      let (line, rest) = lines.split_at(row_len + 1);
+      lines = rest;
+      let filter_type = line[0];
+      if filter_type > 4 {
+        return Err(ImageDataError::InvalidFilterType(filter_type));
+      }
+      let start = raw.len();
+      for (idx, &byte) in line[1..].iter().enumerate() {
+        let left = if idx >= bpp { raw[start + idx - bpp] } else { 0 };
+        let up = if row > 0 { raw[start + idx - row_len] } else { 0 };
+        let up_left = if row > 0 && idx >= bpp { raw[start + idx - row_len - bpp] } else { 0 };
+        raw.push(byte.wrapping_add(predict(filter_type, left, up, up_left)));
+      }
+    }
+    debug_assert_eq!(raw.len() - pass_start, row_len * height);
+  }
+  Ok(raw)
+}
+
+/// Filters raw image data, picking for every scanline the filter with the smallest output
+pub fn filter(header: &ImageHeader, raw: &[u8]) -> result::Result<Vec<u8>, ImageDataError> {
+  let expected_len = raw_len(header)?;
+  if raw.len() != expected_len {
+    return Err(ImageDataError::InvalidLength(expected_len, raw.len()));
+  }
+  let bpp = filter_bpp(header);
+  let mut filtered = Vec::with_capacity(raw.len() + raw.len() / 8);
+  let mut offset = 0;
+  let mut candidate = Vec::new();
+  for (row_len, height) in rows(header)? {
+    for row in 0..height {
+      let line = &raw[offset..offset + row_len];
+      let previous = if row > 0 { Some(&raw[offset - row_len..offset]) } else { None };
+      let mut best: Option<(u64, Vec<u8>)> = None;
+      for filter_type in 0..=4u8 {
+        candidate.clear();
+        candidate.push(filter_type);
+        for (idx, &byte) in line.iter().enumerate() {
+          let left = if idx >= bpp { line[idx - bpp] } else { 0 };
+          let up = previous.map_or(0, |previous| previous[idx]);
+          let up_left = match previous {
+            Some(previous) if idx >= bpp => previous[idx - bpp],
+            _ => 0,
+          };
+          candidate.push(byte.wrapping_sub(predict(filter_type, left, up, up_left)));
+        }
+        let cost = candidate[1..].iter().map(|&byte| (byte as i8).unsigned_abs() as u64).sum();
+        if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+          best = Some((cost, candidate.clone()));
+        }
+      }
+      if let Some((_, line)) = best {
+        filtered.extend(line);
+      }
+      offset += row_len;
+    }
+  }
+  Ok(filtered)
+}
+
+/// Decompresses and unfilters the concatenated data of the IDAT chunks
+pub fn decode(header: &ImageHeader, compressed: &[u8]) -> result::Result<Vec<u8>, ImageDataError> {
+  let expected_len = raw_len(header)?
+    .checked_add(rows(header)?.iter().map(|&(_, height)| height).sum())
+    .ok_or(ImageDataError::TooLarge)?;
+  unfilter(header, &inflate(compressed, expected_len)?)
+}
+
+/// Filters and compresses raw image data into the content of IDAT chunks
+pub fn encode(header: &ImageHeader, raw: &[u8], level: u32) -> result::Result<Vec<u8>, ImageDataError> {
+  Ok(deflate(&filter(header, raw)?, level))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn testing_header(color_type: ColorType, bit_depth: u8, interlace_method: InterlaceMethod) -> ImageHeader {
+    ImageHeader {
+      width: 13,
+      height: 7,
+      bit_depth,
+      color_type,
+      compression_method: 0,
+      filter_method: 0,
+      interlace_method,
+    }
+  }
+
+  fn testing_raw(header: &ImageHeader) -> Vec<u8> {
+    (0..raw_len(header).unwrap())
+      .map(|idx| (idx * 7 % 251) as u8)
+      .collect()
+  }
+
+  #[test]
+  fn test_raw_len() {
+    let header = testing_header(ColorType::Rgb, 8, InterlaceMethod::None);
+    assert_eq!(raw_len(&header).unwrap(), 13 * 3 * 7);
+    let header = testing_header(ColorType::Grayscale, 1, InterlaceMethod::None);
+    assert_eq!(raw_len(&header).unwrap(), 2 * 7);
+    let header = testing_header(ColorType::Rgba, 16, InterlaceMethod::Adam7);
+    let pixels: usize = passes(&header).iter().map(|(width, height)| width * height).sum();
+    assert_eq!(pixels, 13 * 7);
+    assert_eq!(raw_len(&header).unwrap(), 13 * 7 * 8);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for (color_type, bit_depth) in [(ColorType::Rgb, 8), (ColorType::Rgba, 16), (ColorType::Grayscale, 2)] {
+      for interlace_method in [InterlaceMethod::None, InterlaceMethod::Adam7] {
+        let header = testing_header(color_type, bit_depth, interlace_method);
+        let raw = testing_raw(&header);
+        let compressed = encode(&header, &raw, 6).unwrap();
+        assert_eq!(decode(&header, &compressed).unwrap(), raw);
+      }
+    }
+  }
+
+  #[test]
+  fn test_unfilter_all_filter_types() {
+    let header = testing_header(ColorType::Rgb, 8, InterlaceMethod::None);
+    let raw = testing_raw(&header);
+    let row_len = 13 * 3;
+    for filter_type in 0..=4u8 {
+      let mut filtered = Vec::new();
+      for row in 0..7 {
+        let line = &raw[row * row_len..(row + 1) * row_len];
+        filtered.push(filter_type);
+        for idx in 0..row_len {
+          let left = if idx >= 3 { line[idx - 3] } else { 0 };
+          let up = if row > 0 { raw[(row - 1) * row_len + idx] } else { 0 };
+          let up_left = if row > 0 && idx >= 3 { raw[(row - 1) * row_len + idx - 3] } else { 0 };
+          filtered.push(line[idx].wrapping_sub(predict(filter_type, left, up, up_left)));
+        }
+      }
+      assert_eq!(unfilter(&header, &filtered).unwrap(), raw);
+    }
+  }
+
+  #[test]
+  fn test_invalid_image_data() {
+    let header = testing_header(ColorType::Rgb, 8, InterlaceMethod::None);
+    let raw = testing_raw(&header);
+    let mut filtered = filter(&header, &raw).unwrap();
+    assert!(matches!(
+      unfilter(&header, &filtered[1..]),
+      Err(ImageDataError::InvalidLength(_, _))
+    ));
+    filtered[0] = 5;
+    assert!(matches!(
+      unfilter(&header, &filtered),
+      Err(ImageDataError::InvalidFilterType(5))
+    ));
+    assert!(matches!(decode(&header, b"not zlib"), Err(ImageDataError::Inflate(_))));
+  }
+}
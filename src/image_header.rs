@@ -0,0 +1,303 @@
+use std::{error, fmt, result, str::FromStr};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+/// Size of the IHDR chunk data
+const IHDR_LEN: usize = 13;
+
+/// Image header errors
+#[derive(Debug)]
+pub enum ImageHeaderError {
+  /// The chunk is not an IHDR chunk
+  NotImageHeader(ChunkType),
+  /// IHDR data has an incorrect number of bytes (13 expected)
+  InvalidLength(usize),
+  /// Width or height is zero or larger than 2^31 - 1
+  InvalidDimensions(u32, u32),
+  /// Color type is not one of 0, 2, 3, 4 or 6
+  InvalidColorType(u8),
+  /// Bit depth is not allowed for the color type
+  InvalidBitDepth(ColorType, u8),
+  InvalidCompressionMethod(u8),
+  InvalidFilterMethod(u8),
+  InvalidInterlaceMethod(u8),
+}
+
+impl error::Error for ImageHeaderError {}
+
+impl fmt::Display for ImageHeaderError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ImageHeaderError::NotImageHeader(chunk_type) => {
+        write!(f, "Expected an IHDR chunk but found {}", chunk_type)
+      }
+      ImageHeaderError::InvalidLength(actual) => write!(
+        f,
+        "Expected {} bytes of IHDR data but found {}",
+        IHDR_LEN, actual
+      ),
+      ImageHeaderError::InvalidDimensions(width, height) => {
+        write!(f, "Invalid image dimensions {}x{}", width, height)
+      }
+      ImageHeaderError::InvalidColorType(color_type) => {
+        write!(f, "Invalid color type {}", color_type)
+      }
+      ImageHeaderError::InvalidBitDepth(color_type, bit_depth) => write!(
+        f,
+        "Bit depth {} is not allowed for color type {}",
+        bit_depth, color_type
+      ),
+      ImageHeaderError::InvalidCompressionMethod(method) => {
+        write!(f, "Invalid compression method {}", method)
+      }
+      ImageHeaderError::InvalidFilterMethod(method) => write!(f, "Invalid filter method {}", method),
+      ImageHeaderError::InvalidInterlaceMethod(method) => {
+        write!(f, "Invalid interlace method {}", method)
+      }
+    }
+  }
+}
+
+/// How pixels are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+  Grayscale = 0,
+  Rgb = 2,
+  Indexed = 3,
+  GrayscaleAlpha = 4,
+  Rgba = 6,
+}
+
+impl ColorType {
+  /// Bit depths the png spec allows for this color type
+  pub fn allowed_bit_depths(&self) -> &'static [u8] {
+    match self {
+      ColorType::Grayscale => &[1, 2, 4, 8, 16],
+      ColorType::Indexed => &[1, 2, 4, 8],
+      ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+    }
+  }
+}
+
+impl TryFrom<u8> for ColorType {
+  type Error = ImageHeaderError;
+  fn try_from(value: u8) -> result::Result<ColorType, ImageHeaderError> {
+    match value {
+      0 => Ok(ColorType::Grayscale),
+      2 => Ok(ColorType::Rgb),
+      3 => Ok(ColorType::Indexed),
+      4 => Ok(ColorType::GrayscaleAlpha),
+      6 => Ok(ColorType::Rgba),
+      _ => Err(ImageHeaderError::InvalidColorType(value)),
+    }
+  }
+}
+
+impl fmt::Display for ColorType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      ColorType::Grayscale => "grayscale",
+      ColorType::Rgb => "rgb",
+      ColorType::Indexed => "indexed",
+      ColorType::GrayscaleAlpha => "grayscale with alpha",
+      ColorType::Rgba => "rgba",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// Order in which pixels are transmitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterlaceMethod {
+  None = 0,
+  Adam7 = 1,
+}
+
+impl TryFrom<u8> for InterlaceMethod {
+  type Error = ImageHeaderError;
+  fn try_from(value: u8) -> result::Result<InterlaceMethod, ImageHeaderError> {
+    match value {
+      0 => Ok(InterlaceMethod::None),
+      1 => Ok(InterlaceMethod::Adam7),
+      _ => Err(ImageHeaderError::InvalidInterlaceMethod(value)),
+    }
+  }
+}
+
+/// The content of the IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+  pub width: u32,
+  pub height: u32,
+  pub bit_depth: u8,
+  pub color_type: ColorType,
+  /// Always 0 (deflate) in this version of png
+  pub compression_method: u8,
+  /// Always 0 (adaptive filtering) in this version of png
+  pub filter_method: u8,
+  pub interlace_method: InterlaceMethod,
+}
+
+impl ImageHeader {
+  /// Checks the dimensions, methods and bit depth against the color type
+  pub fn validate(&self) -> result::Result<(), ImageHeaderError> {
+    let is_valid_dimension = |dimension: u32| (1..=Chunk::MAX_LENGTH).contains(&dimension);
+    if !is_valid_dimension(self.width) || !is_valid_dimension(self.height) {
+      return Err(ImageHeaderError::InvalidDimensions(self.width, self.height));
+    }
+    if !self.color_type.allowed_bit_depths().contains(&self.bit_depth) {
+      return Err(ImageHeaderError::InvalidBitDepth(self.color_type, self.bit_depth));
+    }
+    if self.compression_method != 0 {
+      return Err(ImageHeaderError::InvalidCompressionMethod(self.compression_method));
+    }
+    if self.filter_method != 0 {
+      return Err(ImageHeaderError::InvalidFilterMethod(self.filter_method));
+    }
+    Ok(())
+  }
+
+  /// Serializes the header as an IHDR chunk
+  pub fn to_chunk(&self) -> Chunk {
+    let chunk_data = self
+      .width
+      .to_be_bytes()
+      .iter()
+      .chain(self.height.to_be_bytes().iter())
+      .chain(
+        [
+          self.bit_depth,
+          self.color_type as u8,
+          self.compression_method,
+          self.filter_method,
+          self.interlace_method as u8,
+        ]
+        .iter(),
+      )
+      .copied()
+      .collect();
+    Chunk::new(ChunkType::from_str("IHDR").unwrap(), chunk_data)
+  }
+}
+
+impl TryFrom<&Chunk> for ImageHeader {
+  type Error = ImageHeaderError;
+  fn try_from(chunk: &Chunk) -> result::Result<ImageHeader, ImageHeaderError> {
+    ImageHeader::try_from((chunk.chunk_type(), chunk.data()))
+  }
+}
+
+impl TryFrom<(&ChunkType, &[u8])> for ImageHeader {
+  type Error = ImageHeaderError;
+  fn try_from((chunk_type, data): (&ChunkType, &[u8])) -> result::Result<ImageHeader, ImageHeaderError> {
+    if chunk_type.bytes() != *b"IHDR" {
+      return Err(ImageHeaderError::NotImageHeader(*chunk_type));
+    }
+    let data: [u8; IHDR_LEN] = data
+      .try_into()
+      .map_err(|_| ImageHeaderError::InvalidLength(data.len()))?;
+    let header = ImageHeader {
+      width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+      height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+      bit_depth: data[8],
+      color_type: ColorType::try_from(data[9])?,
+      compression_method: data[10],
+      filter_method: data[11],
+      interlace_method: InterlaceMethod::try_from(data[12])?,
+    };
+    header.validate()?;
+    Ok(header)
+  }
+}
+
+impl fmt::Display for ImageHeader {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}x{}, {} bit {}",
+      self.width, self.height, self.bit_depth, self.color_type
+    )?;
+    if self.interlace_method == InterlaceMethod::Adam7 {
+      write!(f, ", interlaced")?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn testing_header() -> ImageHeader {
+    ImageHeader {
+      width: 50,
+      height: 40,
+      bit_depth: 8,
+      color_type: ColorType::Rgba,
+      compression_method: 0,
+      filter_method: 0,
+      interlace_method: InterlaceMethod::None,
+    }
+  }
+
+  fn ihdr_chunk(data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str("IHDR").unwrap(), data.to_vec())
+  }
+
+  #[test]
+  fn test_image_header_from_chunk() {
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0]);
+    let header = ImageHeader::try_from(&chunk).unwrap();
+    assert_eq!(header, testing_header());
+  }
+
+  #[test]
+  fn test_image_header_round_trip() {
+    let header = testing_header();
+    let chunk = header.to_chunk();
+    assert_eq!(chunk.length(), 13);
+    assert_eq!(ImageHeader::try_from(&chunk).unwrap(), header);
+  }
+
+  #[test]
+  fn test_image_header_invalid_length() {
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0]);
+    assert!(matches!(
+      ImageHeader::try_from(&chunk),
+      Err(ImageHeaderError::InvalidLength(12))
+    ));
+  }
+
+  #[test]
+  fn test_image_header_invalid_bit_depth() {
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 4, 2, 0, 0, 0]);
+    assert!(matches!(
+      ImageHeader::try_from(&chunk),
+      Err(ImageHeaderError::InvalidBitDepth(ColorType::Rgb, 4))
+    ));
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 16, 3, 0, 0, 0]);
+    assert!(ImageHeader::try_from(&chunk).is_err());
+  }
+
+  #[test]
+  fn test_image_header_invalid_fields() {
+    let chunk = ihdr_chunk(&[0, 0, 0, 0, 0, 0, 0, 40, 8, 6, 0, 0, 0]);
+    assert!(matches!(
+      ImageHeader::try_from(&chunk),
+      Err(ImageHeaderError::InvalidDimensions(0, 40))
+    ));
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 5, 0, 0, 0]);
+    assert!(ImageHeader::try_from(&chunk).is_err());
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 1, 0, 0]);
+    assert!(ImageHeader::try_from(&chunk).is_err());
+    let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 2]);
+    assert!(ImageHeader::try_from(&chunk).is_err());
+  }
+
+  #[test]
+  fn test_image_header_not_ihdr() {
+    let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0; 13]);
+    assert!(ImageHeader::try_from(&chunk).is_err());
+  }
+}
@@ -0,0 +1,50 @@
+//! Hide messages inside png files.
+//!
+//! A png is a signature followed by a sequence of chunks. [`png::Png`] holds a whole file in
+//! memory, [`png::PngRef`] borrows its chunks from a buffer such as a [`mapped::MappedFile`] for
+//! reading, [`stream::PngReader`] and [`stream::PngWriter`] process one chunk at a time, and
+//! [`commands`] implements the operations of the `pngme` command line on top of them, which
+//! [`batch`] runs over many files in parallel.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use std::str::FromStr;
+//!
+//! use pngme::chunk::Chunk;
+//! use pngme::chunk_type::ChunkType;
+//! use pngme::png::ChunkPosition;
+//!
+//! # fn main() -> pngme::Result<()> {
+//! let path = Path::new("image.png");
+//! let chunk = Chunk::new(ChunkType::from_str("ruSt")?, b"hello".to_vec());
+//! pngme::commands::encode(path, path, chunk, ChunkPosition::default(), &Default::default())?;
+//! let message = pngme::commands::decode(path, "ruSt", &[], || Err("not encrypted".into()))?;
+//! assert_eq!(message, "hello");
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod apng;
+pub mod attachment;
+pub mod batch;
+pub mod chunk;
+pub mod chunk_type;
+pub mod commands;
+pub mod crypto;
+pub mod fragment;
+pub mod image_data;
+pub mod image_header;
+pub mod lsb;
+pub mod mapped;
+pub mod png;
+pub mod registry;
+pub mod repair;
+pub mod stream;
+pub mod strip;
+pub mod text;
+#[cfg(test)]
+mod testing;
+pub mod validation;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,298 @@
+//! Hiding payloads in the least significant bits of the pixels
+//!
+//! The payload is prefixed with the magic `PMlb` and its length as a big endian `u32`, and the
+//! bits of the result, most significant first, replace the lowest bit of the selected samples
+//! of every pixel in the order they are stored in the raw image data. Only the low byte of 16 bit
+//! samples is changed. Palette images and bit depths below 8 are not supported, as flipping a bit
+//! of their samples changes the color completely.
+
+use std::{error, fmt, result, str::FromStr};
+
+use crate::image_data;
+use crate::image_header::{ColorType, ImageHeader};
+
+/// Marks image data holding a payload
+const MAGIC: [u8; 4] = *b"PMlb";
+
+/// Size of the magic and the payload length
+const HEADER_LEN: usize = 8;
+
+/// Lsb errors
+#[derive(Debug)]
+pub enum LsbError {
+  /// The color type or bit depth of the image cannot hold a payload
+  UnsupportedImage(ColorType, u8),
+  /// The image has no such channel
+  UnsupportedChannel(char, ColorType),
+  /// A channel letter is not one of r, g, b, a or l
+  InvalidChannel(char),
+  /// The payload needs more bytes than the image can hold
+  Capacity(usize, usize),
+  /// The raw image data is smaller than the image header describes
+  InvalidLength(usize, usize),
+  /// The stored payload length goes past the end of the image
+  Truncated(usize, usize),
+}
+
+impl error::Error for LsbError {}
+
+impl fmt::Display for LsbError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LsbError::UnsupportedImage(color_type, bit_depth) => write!(
+        f,
+        "Cannot hide data in {} bit {} images",
+        bit_depth, color_type
+      ),
+      LsbError::UnsupportedChannel(channel, color_type) => {
+        write!(f, "{} images have no channel {}", color_type, channel)
+      }
+      LsbError::InvalidChannel(channel) => {
+        write!(f, "Invalid channel {}, expected r, g, b, a or l", channel)
+      }
+      LsbError::Capacity(needed, available) => write!(
+        f,
+        "The payload needs {} bytes but the image only holds {}",
+        needed, available
+      ),
+      LsbError::InvalidLength(expected, actual) => write!(
+        f,
+        "Invalid image data length. Expected {} bytes but found {}",
+        expected, actual
+      ),
+      LsbError::Truncated(expected, available) => write!(
+        f,
+        "The hidden payload claims {} bytes but the image only holds {}",
+        expected, available
+      ),
+    }
+  }
+}
+
+/// The channels whose samples carry the payload, given as letters like `rgb` or `la`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Channels(Option<String>);
+
+impl Channels {
+  /// Sample indices within a pixel, every color channel but not alpha by default
+  fn samples(&self, color_type: ColorType) -> result::Result<Vec<usize>, LsbError> {
+    let letters = match color_type {
+      ColorType::Grayscale => "l",
+      ColorType::GrayscaleAlpha => "la",
+      ColorType::Rgb => "rgb",
+      ColorType::Rgba => "rgba",
+      ColorType::Indexed => "",
+    };
+    let Some(channels) = &self.0 else {
+      return Ok((0..letters.len()).filter(|&idx| &letters[idx..=idx] != "a").collect());
+    };
+    channels
+      .chars()
+      .map(|channel| {
+        letters
+          .find(channel)
+          .ok_or(LsbError::UnsupportedChannel(channel, color_type))
+      })
+      .collect()
+  }
+}
+
+impl FromStr for Channels {
+  type Err = LsbError;
+
+  fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+    let mut channels = String::new();
+    for channel in s.chars().map(|channel| channel.to_ascii_lowercase()) {
+      if !"rgbal".contains(channel) {
+        return Err(LsbError::InvalidChannel(channel));
+      }
+      if !channels.contains(channel) {
+        channels.push(channel);
+      }
+    }
+    Ok(Channels(Some(channels)))
+  }
+}
+
+/// Offsets in the raw image data of the bytes whose lowest bit carries the payload
+fn carriers(
+  header: &ImageHeader,
+  raw_len: usize,
+  channels: &Channels,
+) -> result::Result<impl Iterator<Item = usize>, LsbError> {
+  let color_type = header.color_type;
+  if color_type == ColorType::Indexed || header.bit_depth < 8 {
+    return Err(LsbError::UnsupportedImage(color_type, header.bit_depth));
+  }
+  let expected_len = image_data::raw_len(header).unwrap_or(usize::MAX);
+  if raw_len < expected_len {
+    return Err(LsbError::InvalidLength(expected_len, raw_len));
+  }
+  let samples = channels.samples(color_type)?;
+  let sample_len = header.bit_depth as usize / 8;
+  let pixel_len = image_data::channels(color_type) * sample_len;
+  let pixels = expected_len / pixel_len;
+  Ok((0..pixels).flat_map(move |pixel| {
+    samples
+      .clone()
+      .into_iter()
+      .map(move |sample| pixel * pixel_len + sample * sample_len + sample_len - 1)
+  }))
+}
+
+/// Number of payload bytes an image can hold in the given channels
+pub fn capacity(header: &ImageHeader, raw_len: usize, channels: &Channels) -> result::Result<usize, LsbError> {
+  let bits = carriers(header, raw_len, channels)?.count();
+  Ok((bits / 8).saturating_sub(HEADER_LEN))
+}
+
+/// Writes a payload into the raw image data
+pub fn embed(
+  header: &ImageHeader,
+  raw: &mut [u8],
+  channels: &Channels,
+  payload: &[u8],
+) -> result::Result<(), LsbError> {
+  let available = capacity(header, raw.len(), channels)?;
+  if payload.len() > available || u32::try_from(payload.len()).is_err() {
+    return Err(LsbError::Capacity(payload.len(), available));
+  }
+  let length = (payload.len() as u32).to_be_bytes();
+  let bits = MAGIC
+    .iter()
+    .chain(length.iter())
+    .chain(payload.iter())
+    .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1));
+  for (offset, bit) in carriers(header, raw.len(), channels)?.zip(bits) {
+    raw[offset] = (raw[offset] & !1) | bit;
+  }
+  Ok(())
+}
+
+/// Reads the payload hidden in the raw image data, `None` when there is none
+pub fn extract(
+  header: &ImageHeader,
+  raw: &[u8],
+  channels: &Channels,
+) -> result::Result<Option<Vec<u8>>, LsbError> {
+  let available = capacity(header, raw.len(), channels)?;
+  let mut bits = carriers(header, raw.len(), channels)?.map(|offset| raw[offset] & 1);
+  let mut next_byte = || (0..8).try_fold(0u8, |byte, _| Some(byte << 1 | bits.next()?));
+  let mut prefix = [0u8; HEADER_LEN];
+  for byte in prefix.iter_mut() {
+    match next_byte() {
+      Some(value) => *byte = value,
+      None => return Ok(None),
+    }
+  }
+  if prefix[..4] != MAGIC {
+    return Ok(None);
+  }
+  let length = u32::from_be_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]) as usize;
+  if length > available {
+    return Err(LsbError::Truncated(length, available));
+  }
+  let payload = (0..length).map_while(|_| next_byte()).collect();
+  Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::image_header::InterlaceMethod;
+
+  fn testing_header(color_type: ColorType, bit_depth: u8) -> ImageHeader {
+    ImageHeader {
+      width: 20,
+      height: 10,
+      bit_depth,
+      color_type,
+      compression_method: 0,
+      filter_method: 0,
+      interlace_method: InterlaceMethod::None,
+    }
+  }
+
+  fn testing_raw(header: &ImageHeader) -> Vec<u8> {
+    (0..image_data::raw_len(header).unwrap())
+      .map(|idx| (idx * 31 % 256) as u8)
+      .collect()
+  }
+
+  #[test]
+  fn test_capacity() {
+    let header = testing_header(ColorType::Rgb, 8);
+    let raw = testing_raw(&header);
+    assert_eq!(capacity(&header, raw.len(), &Channels::default()).unwrap(), 200 * 3 / 8 - 8);
+    let channels = Channels::from_str("r").unwrap();
+    assert_eq!(capacity(&header, raw.len(), &channels).unwrap(), 200 / 8 - 8);
+    let header = testing_header(ColorType::Rgba, 16);
+    let raw = testing_raw(&header);
+    assert_eq!(capacity(&header, raw.len(), &Channels::default()).unwrap(), 200 * 3 / 8 - 8);
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for (color_type, bit_depth) in [(ColorType::Rgb, 8), (ColorType::Rgba, 16), (ColorType::GrayscaleAlpha, 8)] {
+      let header = testing_header(color_type, bit_depth);
+      let original = testing_raw(&header);
+      let mut raw = original.clone();
+      embed(&header, &mut raw, &Channels::default(), b"hidden").unwrap();
+      assert_eq!(extract(&header, &raw, &Channels::default()).unwrap().unwrap(), b"hidden");
+      assert!(raw
+        .iter()
+        .zip(original.iter())
+        .all(|(changed, original)| changed >> 1 == original >> 1));
+    }
+  }
+
+  #[test]
+  fn test_only_selected_channels_change() {
+    let header = testing_header(ColorType::Rgba, 8);
+    let original = vec![0u8; image_data::raw_len(&header).unwrap()];
+    let mut raw = original.clone();
+    let channels = Channels::from_str("b").unwrap();
+    embed(&header, &mut raw, &channels, &[0xff; 10]).unwrap();
+    let changed: Vec<usize> = (0..raw.len()).filter(|&idx| raw[idx] != original[idx]).collect();
+    assert!(!changed.is_empty());
+    assert!(changed.iter().all(|idx| idx % 4 == 2));
+    assert_eq!(extract(&header, &raw, &channels).unwrap().unwrap(), [0xff; 10]);
+  }
+
+  #[test]
+  fn test_no_payload() {
+    let header = testing_header(ColorType::Rgb, 8);
+    let raw = testing_raw(&header);
+    assert!(extract(&header, &raw, &Channels::default()).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_payload_too_large() {
+    let header = testing_header(ColorType::Rgb, 8);
+    let mut raw = testing_raw(&header);
+    let available = capacity(&header, raw.len(), &Channels::default()).unwrap();
+    assert!(matches!(
+      embed(&header, &mut raw, &Channels::default(), &vec![0; available + 1]),
+      Err(LsbError::Capacity(_, _))
+    ));
+    assert!(embed(&header, &mut raw, &Channels::default(), &vec![0; available]).is_ok());
+  }
+
+  #[test]
+  fn test_unsupported() {
+    let header = testing_header(ColorType::Indexed, 8);
+    let mut raw = testing_raw(&header);
+    assert!(matches!(
+      embed(&header, &mut raw, &Channels::default(), b"x"),
+      Err(LsbError::UnsupportedImage(ColorType::Indexed, 8))
+    ));
+    let header = testing_header(ColorType::Rgb, 8);
+    let mut raw = testing_raw(&header);
+    let channels = Channels::from_str("a").unwrap();
+    assert!(matches!(
+      embed(&header, &mut raw, &channels, b"x"),
+      Err(LsbError::UnsupportedChannel('a', ColorType::Rgb))
+    ));
+    assert!(matches!(Channels::from_str("rx"), Err(LsbError::InvalidChannel('x'))));
+  }
+}
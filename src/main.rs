@@ -1,35 +1,411 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
 use clap::Parser;
+use serde::Serialize;
+
+use pngme::attachment::Attachment;
+use pngme::batch;
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::commands::{self, WriteOptions};
+use pngme::crypto::{self, PublicKey};
+use pngme::fragment;
+use pngme::strip::StripFilter;
+use pngme::text::{TextChunk, TextChunkError};
+use pngme::Result;
 
 mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+mod output;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+fn write_identity(identity_path: &Path, identity: &str) -> Result<()> {
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options.open(identity_path)?.write_all(identity.as_bytes())?;
+  Ok(())
+}
 
-fn main() -> Result<()> {
-  let cli = args::Cli::parse();
+/// Chunks holding the payload, split in fragments when it is larger than `max_chunk_size`
+fn payload_chunks(chunk_type: &str, payload: Vec<u8>, max_chunk_size: Option<usize>) -> Result<Vec<Chunk>> {
+  let chunk_type = ChunkType::from_str(chunk_type)?;
+  let max_chunk_size = max_chunk_size.unwrap_or(Chunk::MAX_LENGTH as usize);
+  if payload.len() <= max_chunk_size {
+    return Ok(vec![Chunk::new(chunk_type, payload)]);
+  }
+  let fragments = fragment::split(&payload, max_chunk_size)?;
+  Ok(fragments.into_iter().map(|data| Chunk::new(chunk_type, data)).collect())
+}
+
+/// A text metadata chunk of the given type holding the message
+fn text_chunk(chunk_type: &str, keyword: &str, message: &str) -> Result<Chunk> {
+  let chunk_type = ChunkType::from_str(chunk_type)?;
+  let text = match &chunk_type.bytes() {
+    b"tEXt" => TextChunk::new(keyword, message, false),
+    b"zTXt" => TextChunk::new(keyword, message, true),
+    b"iTXt" => TextChunk::international(keyword, message, false),
+    _ => return Err(TextChunkError::NotTextChunk(chunk_type).into()),
+  };
+  Ok(text.to_chunk()?)
+}
+
+fn write_options(args: &args::WriteArgs) -> WriteOptions {
+  WriteOptions {
+    backup: args.backup.clone(),
+    preserve: args.preserve,
+    dry_run: args.dry_run,
+    keep_unsafe: args.keep_unsafe,
+  }
+}
+
+/// The message given as an argument or read from a file or stdin
+fn message(args: &args::EncodeArgs) -> Result<String> {
+  match (&args.message, &args.message_file) {
+    (Some(message), _) => Ok(message.clone()),
+    (None, Some(message_file)) => {
+      if commands::is_stdio(message_file) && commands::is_stdio(&args.file_path) {
+        return Err("stdin cannot hold both the png and the message".into());
+      }
+      let message = commands::read_input(message_file)?;
+      Ok(String::from_utf8(message).map_err(|_| "the message is not valid UTF-8 text")?)
+    }
+    (None, None) => Err("a message or --message-file is required".into()),
+  }
+}
+
+/// The file to embed, read from a file or stdin
+fn attachment(args: &args::EmbedArgs) -> Result<Attachment> {
+  if commands::is_stdio(&args.attachment_path) && commands::is_stdio(&args.file_path) {
+    return Err("stdin cannot hold both the png and the file to embed".into());
+  }
+  let filename = match args.no_filename || commands::is_stdio(&args.attachment_path) {
+    true => None,
+    false => args
+      .attachment_path
+      .file_name()
+      .map(|filename| filename.to_string_lossy().into_owned()),
+  };
+  Ok(Attachment {
+    filename,
+    mime_type: args.mime_type.clone(),
+    data: commands::read_input(&args.attachment_path)?,
+  })
+}
+
+fn passphrase(passphrase: Option<String>) -> Result<String> {
+  match passphrase {
+    Some(passphrase) => Ok(passphrase),
+    None => Ok(rpassword::prompt_password("Passphrase: ")?),
+  }
+}
+
+/// Reads the passphrase once, when a file first needs it
+fn cached_passphrase(cache: &Mutex<Option<String>>) -> Result<String> {
+  let mut cache = cache.lock().map_err(|_| "the passphrase cannot be read")?;
+  if cache.is_none() {
+    *cache = Some(passphrase(None)?);
+  }
+  Ok(cache.clone().unwrap_or_default())
+}
+
+/// Runs `command` on the file path, or on every file named by the batch arguments in parallel
+///
+/// `command` returns the exit code and report for a file. `output` is where the png of a single
+/// file is written, its report going to stderr when that is stdout; several files can only be
+/// changed in place.
+fn each_file<T, F>(
+  format: output::Format,
+  file_path: &Path,
+  batch: &args::BatchArgs,
+  output: Option<&Path>,
+  command: F,
+) -> Result<i32>
+where
+  T: Serialize + fmt::Display,
+  F: Fn(&Path) -> Result<(i32, T)> + Sync,
+{
+  if !batch.is_batch(file_path) {
+    let (code, report) = command(file_path)?;
+    match output {
+      Some(output) => output::emit_for(format, output, &report),
+      None => output::emit(format, &report),
+    }
+    return Ok(code);
+  }
+  if output.is_some_and(|output| output != file_path) {
+    return Err("several files can only be changed in place".into());
+  }
+  let files = batch.expand(file_path)?;
+  let reports = batch::run(&files, batch.jobs, |path| match command(path) {
+    Ok((code, report)) => output::FileReport::new(path, code, &report),
+    Err(error) => output::FileReport::failed(path, output::exit_code(error.as_ref()), error.as_ref()),
+  })?;
+  let batch = output::Batch::new(reports);
+  output::emit(format, &batch);
+  Ok(batch.code())
+}
+
+fn run(cli: args::Cli) -> Result<i32> {
+  let format = cli.format;
   match cli.command {
     args::Commands::Encode(args) => {
-      commands::encode(&args)?;
+      let options = write_options(&args.write);
+      let message = message(&args)?;
+      let channels = args.channels.clone().unwrap_or_default();
+      let mut chunk_data = Vec::new();
+      let chunks = match (&args.text_keyword, args.mode) {
+        (Some(_), args::Mode::Lsb) => return Err("--text-keyword cannot be used with --mode lsb".into()),
+        (Some(keyword), args::Mode::Chunk) => vec![text_chunk(&args.chunk_type, keyword, &message)?],
+        (None, mode) => {
+          chunk_data = message.into_bytes();
+          if args.encrypt {
+            chunk_data = crypto::seal(&chunk_data, passphrase(args.passphrase.clone())?.as_bytes())?;
+          } else if !args.recipients.is_empty() {
+            let recipients = args
+              .recipients
+              .iter()
+              .map(|path| Ok(crypto::parse_public_key(&fs::read_to_string(path)?)?))
+              .collect::<Result<Vec<_>>>()?;
+            chunk_data = crypto::seal_for(&chunk_data, &recipients)?;
+          }
+          match mode {
+            args::Mode::Chunk => payload_chunks(&args.chunk_type, chunk_data.clone(), args.max_chunk_size)?,
+            args::Mode::Lsb => Vec::new(),
+          }
+        },
+      };
+      let output = args.output.as_deref().unwrap_or(&args.file_path);
+      return each_file(format, &args.file_path, &args.batch, Some(output), |path| {
+        let output = args.output.as_deref().unwrap_or(path);
+        let (index, discarded) = match args.mode {
+          args::Mode::Chunk => {
+            let (index, discarded) = commands::encode_chunks(path, output, chunks.clone(), args.position, &options)?;
+            (Some(index), discarded)
+          },
+          args::Mode::Lsb => (None, commands::encode_lsb(path, output, &chunk_data, &channels, &options)?),
+        };
+        Ok((output::SUCCESS, output::Encoded {
+          output: output.display().to_string(),
+          index,
+          chunks: chunks.len(),
+          discarded: discarded.iter().map(output::ChunkSummary::from).collect(),
+          dry_run: options.dry_run,
+        }))
+      });
     },
     args::Commands::Decode(args) => {
-      match commands::decode(&args) {
-        Some(msg) => {
-          println!("The message in chunk {:} is [{:}]" , args.chunk_type, msg)
-        },
-        None => println!("This is no message for chunk {:}", args.chunk_type)
+      let identities = args
+        .identities
+        .iter()
+        .map(|path| Ok(crypto::parse_identity(&fs::read_to_string(path)?)?))
+        .collect::<Result<Vec<_>>>()?;
+      let cache = Mutex::new(args.passphrase.clone());
+      let channels = args.channels.clone().unwrap_or_default();
+      return each_file(format, &args.file_path, &args.batch, None, |path| {
+        let passphrase = || cached_passphrase(&cache);
+        let message = match args.mode {
+          args::Mode::Chunk => commands::decode(path, &args.chunk_type, &identities, passphrase),
+          args::Mode::Lsb => commands::decode_lsb(path, &channels, &identities, passphrase),
+        };
+        let decoded = |message: Option<String>| output::Decoded {
+          chunk_type: args.chunk_type.clone(),
+          found: message.is_some(),
+          message,
+        };
+        match message {
+          Ok(message) => Ok((output::SUCCESS, decoded(Some(message)))),
+          Err(error) if output::decode_exit_code(&error) == output::NOT_FOUND => Ok((output::NOT_FOUND, decoded(None))),
+          Err(error) => Err(error.into()),
+        }
+      });
+    },
+    args::Commands::Embed(args) => {
+      let attachment = attachment(&args)?;
+      let chunks = payload_chunks(&args.chunk_type, attachment.to_bytes()?, args.max_chunk_size)?;
+      let count = chunks.len();
+      let output = args.output.as_ref().unwrap_or(&args.file_path);
+      let options = write_options(&args.write);
+      let (index, discarded) = commands::encode_chunks(&args.file_path, output, chunks, args.position, &options)?;
+      output::emit_for(format, output, &output::Encoded {
+        output: output.display().to_string(),
+        index: Some(index),
+        chunks: count,
+        discarded: discarded.iter().map(output::ChunkSummary::from).collect(),
+        dry_run: options.dry_run,
+      });
+    },
+    args::Commands::Extract(args) => {
+      let attachment = commands::extract(&args.file_path, &args.chunk_type)?;
+      if let Some(attachment) = &attachment {
+        match commands::is_stdio(&args.output) {
+          true => std::io::stdout().write_all(&attachment.data)?,
+          false => fs::write(&args.output, &attachment.data)?,
+        }
+      }
+      let found = attachment.is_some();
+      output::emit_for(format, &args.output, &output::Extracted {
+        chunk_type: args.chunk_type,
+        found,
+        output: found.then(|| args.output.display().to_string()),
+        summary: attachment.as_ref().map(|attachment| attachment.to_string()),
+        size: attachment.as_ref().map(|attachment| attachment.data.len()),
+        filename: attachment.as_ref().and_then(|attachment| attachment.filename.clone()),
+        mime_type: attachment.and_then(|attachment| attachment.mime_type),
+      });
+      if !found {
+        return Ok(output::NOT_FOUND);
       }
-       
     },
     args::Commands::Remove(args) => {
-      commands::remove(&args)?;
+      let options = write_options(&args.write);
+      return each_file(format, &args.file_path, &args.batch, Some(&args.file_path), |path| {
+        let (removed, discarded) = commands::remove(path, &args.chunk_type, &options)?;
+        Ok((output::SUCCESS, output::Removed {
+          removed: vec![output::ChunkSummary::from(&removed)],
+          discarded: discarded.iter().map(output::ChunkSummary::from).collect(),
+          dry_run: options.dry_run,
+        }))
+      });
+    },
+    args::Commands::Strip(args) => {
+      let filter = StripFilter {
+        keep: args.keep.clone(),
+        remove: args.remove.clone(),
+      };
+      let options = write_options(&args.write);
+      let output = args.output.as_deref().unwrap_or(&args.file_path);
+      return each_file(format, &args.file_path, &args.batch, Some(output), |path| {
+        let output = args.output.as_deref().unwrap_or(path);
+        let removed = commands::strip(path, output, &filter, &options)?;
+        Ok((output::SUCCESS, output::Removed {
+          removed: removed.iter().map(output::ChunkSummary::from).collect(),
+          discarded: Vec::new(),
+          dry_run: options.dry_run,
+        }))
+      });
+    },
+    args::Commands::Repair(args) => {
+      let output = args.output();
+      let options = write_options(&args.write);
+      let repairs = commands::repair(&args.file_path, &output, args.fix_crc, &options)?;
+      output::emit_for(format, &output, &output::Repaired {
+        repairs: repairs.iter().map(output::RepairInfo::from).collect(),
+        dry_run: options.dry_run,
+      });
     },
     args::Commands::Print(args) => {
-      commands::print_chunks(&args)?;
+      return each_file(format, &args.file_path, &args.batch, None, |path| {
+        let chunks = commands::inspect(path, |png| {
+          png.verify_all()?;
+          Ok(
+            png
+              .chunks()
+              .iter()
+              .enumerate()
+              .map(|(index, chunk)| output::ChunkInfo::new(index, png.offset(index), chunk))
+              .collect(),
+          )
+        })?;
+        Ok((output::SUCCESS, output::Printed { chunks }))
+      });
+    },
+    args::Commands::Keygen(args) => {
+      let identity = crypto::generate_identity();
+      let public_key = PublicKey::from(&identity);
+      let mut public_key_path = args.identity_path.as_os_str().to_owned();
+      public_key_path.push(".pub");
+      write_identity(&args.identity_path, &crypto::format_identity(&identity))?;
+      let public_key = crypto::format_public_key(&public_key);
+      fs::write(&public_key_path, &public_key)?;
+      output::emit(format, &output::Generated {
+        identity_path: args.identity_path.display().to_string(),
+        public_key_path: Path::new(&public_key_path).display().to_string(),
+        public_key: public_key
+          .lines()
+          .filter(|line| !line.starts_with('#'))
+          .collect(),
+      });
+    },
+    args::Commands::Validate(args) => {
+      let violations = commands::validate(&args.file_path)?;
+      let validated = output::Validated::new(&violations);
+      output::emit(format, &validated);
+      if !validated.valid {
+        return Ok(output::INVALID);
+      }
     },
   };
-  Ok(())
+  Ok(output::SUCCESS)
+}
+
+fn main() {
+  let cli = args::Cli::parse();
+  let format = cli.format;
+  let code = run(cli).unwrap_or_else(|error| {
+    output::emit_error(format, error.as_ref());
+    output::exit_code(error.as_ref())
+  });
+  std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> args::Commands {
+    args::Cli::try_parse_from([&["pngme"], args].concat()).unwrap().command
+  }
+
+  fn encode_args(args: &[&str]) -> args::EncodeArgs {
+    match parse(&[&["encode"], args].concat()) {
+      args::Commands::Encode(args) => args,
+      command => panic!("{:?}", command),
+    }
+  }
+
+  fn embed_args(args: &[&str]) -> args::EmbedArgs {
+    match parse(&[&["embed"], args].concat()) {
+      args::Commands::Embed(args) => args,
+      command => panic!("{:?}", command),
+    }
+  }
+
+  #[test]
+  fn test_message_file() {
+    let path = std::env::temp_dir().join(format!("pngme-{}-message.txt", std::process::id()));
+    fs::write(&path, "hello from a file").unwrap();
+    let message_file = path.to_str().unwrap();
+    let result = message(&encode_args(&["a.png", "ruSt", "--message-file", message_file]));
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap(), "hello from a file");
+    assert_eq!(message(&encode_args(&["a.png", "ruSt", "hello"])).unwrap(), "hello");
+  }
+
+  #[test]
+  fn test_message_stdin_twice() {
+    let error = message(&encode_args(&["-", "ruSt", "--message-file", "-"])).unwrap_err();
+    assert_eq!(error.to_string(), "stdin cannot hold both the png and the message");
+  }
+
+  #[test]
+  fn test_attachment_stdin_twice() {
+    let error = attachment(&embed_args(&["-", "ruSt", "-"])).unwrap_err();
+    assert_eq!(error.to_string(), "stdin cannot hold both the png and the file to embed");
+  }
+
+  #[test]
+  fn test_repair_output() {
+    let repair_args = |args: &[&str]| match parse(&[&["repair"], args].concat()) {
+      args::Commands::Repair(args) => args,
+      command => panic!("{:?}", command),
+    };
+    assert_eq!(repair_args(&["shots/a.png"]).output(), Path::new("shots/a.repaired.png"));
+    assert_eq!(repair_args(&["a.png", "-o", "b.png"]).output(), Path::new("b.png"));
+    assert_eq!(repair_args(&["-"]).output(), Path::new("-"));
+  }
 }
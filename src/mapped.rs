@@ -0,0 +1,70 @@
+//! Png files mapped into memory for reading
+//!
+//! The operating system only loads the pages of a mapped file that are read, so parsing the chunk
+//! headers of a [`MappedFile`] with [`PngRef`] leaves the data of the chunks nobody reads on disk.
+//! Only lookups by type, as in `decode`, benefit: checking every crc or parsing every chunk reads
+//! the whole file anyway.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+use std::result;
+
+use memmap2::Mmap;
+
+use crate::png::{PngError, PngRef};
+
+/// A read-only mapping of a whole file
+#[derive(Debug)]
+pub struct MappedFile(Mmap);
+
+impl MappedFile {
+  /// Maps the file at `file_path`
+  ///
+  /// # Safety
+  ///
+  /// The file must not be truncated or written to, by this or any other process, while it is
+  /// mapped, as the mapped bytes would change under the borrows of the mapping. pngme itself
+  /// replaces files by renaming a new file over them, which leaves existing mappings intact.
+  pub unsafe fn open(file_path: &Path) -> io::Result<MappedFile> {
+    let file = File::open(file_path)?;
+    // SAFETY: the mapping is only read, and the caller keeps the file unchanged
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(MappedFile(map))
+  }
+
+  /// Borrows the chunks of the mapped png
+  pub fn png(&self) -> result::Result<PngRef<'_>, PngError> {
+    PngRef::parse(self)
+  }
+}
+
+impl Deref for MappedFile {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::TestingFile;
+  use std::fs;
+
+  #[test]
+  fn test_mapped_file() {
+    let file = TestingFile::new("mapped", &[("ruSt", b"hello"), ("IEND", b"")]);
+    // SAFETY: nothing else writes the file of the test
+    let mapped = unsafe { MappedFile::open(&file.0) }.unwrap();
+    assert_eq!(mapped.len() as u64, fs::metadata(&file.0).unwrap().len());
+    let chunk = mapped.png().unwrap().chunk_by_type("ruSt").unwrap().unwrap();
+    assert_eq!(chunk.data(), b"hello");
+    drop(mapped);
+    let path = file.0.clone();
+    drop(file);
+    assert!(unsafe { MappedFile::open(&path) }.is_err());
+  }
+}
@@ -0,0 +1,852 @@
+//! What the subcommands print, as sentences or as JSON
+//!
+//! Every report serializes to a single JSON object on stdout, or on stderr when the png itself is
+//! written to stdout. Fields are only ever added, never renamed or removed, and failures are
+//! reported as `{"error": "..."}`.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use pngme::apng::{self, AnimationControl, FrameControl};
+use pngme::attachment::Attachment;
+use pngme::chunk::{Chunk, ChunkRef};
+use pngme::commands::{self, DecodeError};
+use pngme::crypto;
+use pngme::fragment::Fragment;
+use pngme::image_header::{ImageHeader, InterlaceMethod};
+use pngme::repair::{Damage, Repair};
+use pngme::text::TextChunk;
+use pngme::validation::{Severity, Violation};
+
+/// The command succeeded
+pub const SUCCESS: i32 = 0;
+/// The command failed, the error is reported
+pub const FAILURE: i32 = 1;
+/// There is no message or file for the chunk type
+pub const NOT_FOUND: i32 = 3;
+/// The png breaks a rule of the spec
+pub const INVALID: i32 = 4;
+/// The file does not exist or cannot be opened
+pub const CANNOT_OPEN: i32 = 5;
+/// The file is not a png or a chunk is malformed
+pub const NOT_PNG: i32 = 6;
+/// The message is not UTF-8 text
+pub const NOT_UTF8: i32 = 7;
+/// The message cannot be decrypted
+pub const DECRYPTION_FAILED: i32 = 8;
+
+/// Exit code of a failed decode
+pub fn decode_exit_code(error: &DecodeError) -> i32 {
+  match error {
+    DecodeError::ChunkNotFound(_) | DecodeError::NoHiddenMessage => NOT_FOUND,
+    DecodeError::CannotOpen(_) => CANNOT_OPEN,
+    DecodeError::NotPng | DecodeError::Malformed(_) => NOT_PNG,
+    DecodeError::NotUtf8 => NOT_UTF8,
+    DecodeError::Crypto(_) | DecodeError::Passphrase(_) => DECRYPTION_FAILED,
+    DecodeError::InvalidFragments(_) | DecodeError::Lsb(_) | DecodeError::Io(_) | DecodeError::Png(_) => FAILURE,
+  }
+}
+
+/// Exit code of a failed command
+pub fn exit_code(error: &(dyn std::error::Error + 'static)) -> i32 {
+  match error.downcast_ref::<DecodeError>() {
+    Some(error) => decode_exit_code(error),
+    None => FAILURE,
+  }
+}
+
+/// How reports are printed
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+  /// Sentences meant for people
+  #[default]
+  Text,
+  /// One JSON object meant for scripts
+  Json,
+}
+
+/// Prints a report in the given format
+pub fn emit<T: Serialize + fmt::Display>(format: Format, report: &T) {
+  emit_to(&mut io::stdout(), format, report);
+}
+
+/// Prints a report about a file written to `output`, on stderr when that file is stdout
+pub fn emit_for<T: Serialize + fmt::Display>(format: Format, output: &Path, report: &T) {
+  match commands::is_stdio(output) {
+    true => emit_to(&mut io::stderr(), format, report),
+    false => emit(format, report),
+  }
+}
+
+fn emit_to<T: Serialize + fmt::Display>(writer: &mut dyn Write, format: Format, report: &T) {
+  let text = match format {
+    Format::Text => report.to_string(),
+    Format::Json => match serde_json::to_string(report) {
+      Ok(json) => json,
+      Err(error) => return eprintln!("error: {}", error),
+    },
+  };
+  if !text.is_empty() {
+    let _ = writeln!(writer, "{}", text);
+  }
+}
+
+/// Prints an error in the given format
+pub fn emit_error(format: Format, error: &dyn std::error::Error) {
+  match format {
+    Format::Text => eprintln!("error: {}", error),
+    Format::Json => println!("{}", serde_json::json!({ "error": error.to_string() })),
+  }
+}
+
+/// Type, length and crc of a chunk
+#[derive(Serialize)]
+pub struct ChunkSummary {
+  pub chunk_type: String,
+  pub length: u32,
+  pub crc: u32,
+}
+
+impl From<&Chunk> for ChunkSummary {
+  fn from(chunk: &Chunk) -> ChunkSummary {
+    ChunkSummary {
+      chunk_type: chunk.chunk_type().to_string(),
+      length: chunk.length(),
+      crc: chunk.crc(),
+    }
+  }
+}
+
+/// The decoded data of a chunk
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Content {
+  Empty,
+  /// IDAT data, only described by the IHDR chunk
+  ImageData,
+  ImageHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: String,
+    interlaced: bool,
+    #[serde(skip)]
+    header: ImageHeader,
+  },
+  Text {
+    keyword: String,
+    language_tag: Option<String>,
+    translated_keyword: Option<String>,
+    text: String,
+    compressed: bool,
+    #[serde(skip)]
+    summary: String,
+  },
+  AnimationControl {
+    num_frames: u32,
+    num_plays: u32,
+    #[serde(skip)]
+    control: AnimationControl,
+  },
+  FrameControl {
+    sequence_number: u32,
+    width: u32,
+    height: u32,
+    x_offset: u32,
+    y_offset: u32,
+    delay_ms: u128,
+    dispose_op: String,
+    blend_op: String,
+    #[serde(skip)]
+    control: FrameControl,
+  },
+  /// fdAT data, only described by the fcTL chunk of its frame
+  FrameData {
+    sequence_number: u32,
+    size: usize,
+  },
+  Fragment {
+    id: u32,
+    index: u32,
+    count: u32,
+    size: usize,
+  },
+  Attachment {
+    filename: Option<String>,
+    mime_type: Option<String>,
+    size: usize,
+    #[serde(skip)]
+    summary: String,
+  },
+  Encrypted {
+    size: usize,
+    base64: String,
+  },
+  Message {
+    text: String,
+  },
+  Binary {
+    size: usize,
+    base64: String,
+  },
+  /// The data looks like one of the formats but does not parse
+  Invalid {
+    format: &'static str,
+    error: String,
+  },
+}
+
+impl From<&ChunkRef<'_>> for Content {
+  fn from(chunk: &ChunkRef<'_>) -> Content {
+    let data = chunk.data();
+    let parts = (chunk.chunk_type(), data);
+    let invalid = |format, error: &dyn std::error::Error| Content::Invalid {
+      format,
+      error: error.to_string(),
+    };
+    match &chunk.chunk_type().bytes() {
+      b"IDAT" => return Content::ImageData,
+      b"IHDR" => {
+        return match ImageHeader::try_from(parts) {
+          Ok(header) => Content::ImageHeader {
+            width: header.width,
+            height: header.height,
+            bit_depth: header.bit_depth,
+            color_type: header.color_type.to_string(),
+            interlaced: header.interlace_method == InterlaceMethod::Adam7,
+            header,
+          },
+          Err(error) => invalid("image header", &error),
+        }
+      }
+      b"acTL" => {
+        return match AnimationControl::try_from(parts) {
+          Ok(control) => Content::AnimationControl {
+            num_frames: control.num_frames,
+            num_plays: control.num_plays,
+            control,
+          },
+          Err(error) => invalid("animation control", &error),
+        }
+      }
+      b"fcTL" => {
+        return match FrameControl::try_from(parts) {
+          Ok(control) => Content::FrameControl {
+            sequence_number: control.sequence_number,
+            width: control.width,
+            height: control.height,
+            x_offset: control.x_offset,
+            y_offset: control.y_offset,
+            delay_ms: control.delay().as_millis(),
+            dispose_op: control.dispose_op.to_string(),
+            blend_op: control.blend_op.to_string(),
+            control,
+          },
+          Err(error) => invalid("frame control", &error),
+        }
+      }
+      b"fdAT" => {
+        return match apng::sequence_number(chunk.chunk_type(), data) {
+          Some(sequence_number) => Content::FrameData {
+            sequence_number,
+            size: data.len() - 4,
+          },
+          None => Content::Invalid {
+            format: "frame data",
+            error: "missing sequence number".to_owned(),
+          },
+        }
+      }
+      _ => {}
+    }
+    if TextChunk::is_text_chunk(chunk.chunk_type()) {
+      return match TextChunk::try_from(parts) {
+        Ok(text) => Content::Text {
+          summary: text.to_string(),
+          keyword: text.keyword,
+          language_tag: text.language_tag,
+          translated_keyword: text.translated_keyword,
+          text: text.text,
+          compressed: text.compressed,
+        },
+        Err(error) => invalid("text chunk", &error),
+      };
+    }
+    if Fragment::is_fragment(data) {
+      return match Fragment::try_from(data) {
+        Ok(fragment) => Content::Fragment {
+          id: fragment.id,
+          index: fragment.index,
+          count: fragment.count,
+          size: fragment.data.len(),
+        },
+        Err(error) => invalid("fragment", &error),
+      };
+    }
+    if Attachment::is_attachment(data) {
+      return match Attachment::try_from(data) {
+        Ok(attachment) => Content::Attachment {
+          summary: attachment.to_string(),
+          size: attachment.data.len(),
+          filename: attachment.filename,
+          mime_type: attachment.mime_type,
+        },
+        Err(error) => invalid("attachment", &error),
+      };
+    }
+    if crypto::is_sealed(data) {
+      return Content::Encrypted {
+        size: data.len(),
+        base64: BASE64.encode(data),
+      };
+    }
+    match std::str::from_utf8(data) {
+      Ok("") => Content::Empty,
+      Ok(text) => Content::Message { text: text.to_owned() },
+      Err(_) => Content::Binary {
+        size: data.len(),
+        base64: BASE64.encode(data),
+      },
+    }
+  }
+}
+
+impl fmt::Display for Content {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Content::Empty | Content::ImageData => Ok(()),
+      Content::ImageHeader { header, .. } => write!(f, "{}", header),
+      Content::AnimationControl { control, .. } => write!(f, "an animation of {}", control),
+      Content::FrameControl { control, .. } => {
+        write!(f, "sequence {}: {}", control.sequence_number, control)
+      }
+      Content::FrameData { sequence_number, size } => {
+        write!(f, "sequence {}, {} bytes", sequence_number, size)
+      }
+      Content::Text { summary, .. } => write!(f, "{}", summary),
+      Content::Fragment { id, index, count, size } => write!(
+        f,
+        "fragment {} of {} of payload {:08x}, {} bytes",
+        index + 1,
+        count,
+        id,
+        size
+      ),
+      Content::Attachment { summary, .. } => write!(f, "an attached file {}", summary),
+      Content::Encrypted { size, .. } => write!(f, "an encrypted message of {} bytes", size),
+      Content::Message { text } if text.trim().is_empty() => Ok(()),
+      Content::Message { text } => write!(f, "the msg is {}", text),
+      Content::Binary { size, .. } => write!(f, "binary data of {} bytes", size),
+      Content::Invalid { format, error } => write!(f, "an invalid {}: {}", format, error),
+    }
+  }
+}
+
+/// A chunk with its place in the file, its property bits and its decoded data
+#[derive(Serialize)]
+pub struct ChunkInfo {
+  pub index: usize,
+  pub offset: usize,
+  pub chunk_type: String,
+  /// What the chunk holds, for chunk types of the png spec and its registered extensions
+  pub name: Option<&'static str>,
+  /// Where that chunk type is specified
+  pub section: Option<&'static str>,
+  pub length: u32,
+  pub crc: u32,
+  pub critical: bool,
+  pub public: bool,
+  pub reserved_bit_valid: bool,
+  pub safe_to_copy: bool,
+  pub content: Content,
+}
+
+impl ChunkInfo {
+  pub fn new(index: usize, offset: usize, chunk: &ChunkRef<'_>) -> ChunkInfo {
+    let chunk_type = chunk.chunk_type();
+    let definition = chunk_type.definition();
+    ChunkInfo {
+      index,
+      offset,
+      chunk_type: chunk_type.to_string(),
+      name: definition.map(|definition| definition.name),
+      section: definition.map(|definition| definition.section),
+      length: chunk.length(),
+      crc: chunk.crc(),
+      critical: chunk_type.is_critical(),
+      public: chunk_type.is_public(),
+      reserved_bit_valid: chunk_type.is_reserved_bit_valid(),
+      safe_to_copy: chunk_type.is_safe_to_copy(),
+      content: Content::from(chunk),
+    }
+  }
+}
+
+/// The chunks listed by `print`
+#[derive(Serialize)]
+pub struct Printed {
+  pub chunks: Vec<ChunkInfo>,
+}
+
+impl fmt::Display for Printed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut lines = Vec::new();
+    for chunk in &self.chunks {
+      match &chunk.content {
+        Content::ImageHeader { .. } => lines.push(format!("the image is {}", chunk.content)),
+        Content::Invalid { error, .. } if chunk.chunk_type == "IHDR" => {
+          lines.push(format!("the image header is invalid: {}", error))
+        }
+        content => {
+          let description = content.to_string();
+          if description.is_empty() {
+            continue;
+          }
+          let name = match chunk.name {
+            Some(name) => name,
+            None if chunk.public => "unknown",
+            None => "private",
+          };
+          lines.push(format!("the chunk type is {} ({}), {}", chunk.chunk_type, name, description));
+        }
+      }
+    }
+    write!(f, "{}", lines.join("\n"))
+  }
+}
+
+/// Where `encode` or `embed` saved the png
+#[derive(Serialize)]
+pub struct Encoded {
+  pub output: String,
+  /// Index of the first inserted chunk, `None` when the pixels carry the message
+  pub index: Option<usize>,
+  /// Number of inserted chunks
+  pub chunks: usize,
+  /// Unsafe-to-copy chunks left out because critical chunks changed
+  pub discarded: Vec<ChunkSummary>,
+  /// Whether nothing was written
+  pub dry_run: bool,
+}
+
+/// Describes the chunks discarded by an edit
+fn discarded_lines(discarded: &[ChunkSummary], dry_run: bool) -> impl Iterator<Item = String> + '_ {
+  let verb = match dry_run {
+    true => "would discard",
+    false => "discarded",
+  };
+  discarded.iter().map(move |chunk| {
+    format!("{} unsafe-to-copy chunk {} of {} bytes", verb, chunk.chunk_type, chunk.length)
+  })
+}
+
+impl fmt::Display for Encoded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut lines = Vec::new();
+    match (self.dry_run, self.index) {
+      (false, _) => {}
+      (true, Some(index)) => lines.push(format!(
+        "would insert {} chunks at index {} and write {}",
+        self.chunks, index, self.output
+      )),
+      (true, None) => lines.push(format!("would hide the message in the pixels and write {}", self.output)),
+    }
+    lines.extend(discarded_lines(&self.discarded, self.dry_run));
+    write!(f, "{}", lines.join("\n"))
+  }
+}
+
+/// The message found by `decode`
+#[derive(Serialize)]
+pub struct Decoded {
+  pub chunk_type: String,
+  pub found: bool,
+  pub message: Option<String>,
+}
+
+impl fmt::Display for Decoded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.message {
+      Some(message) => write!(f, "The message in chunk {} is [{}]", self.chunk_type, message),
+      None => write!(f, "This is no message for chunk {}", self.chunk_type),
+    }
+  }
+}
+
+/// The file written by `extract`
+#[derive(Serialize)]
+pub struct Extracted {
+  pub chunk_type: String,
+  pub found: bool,
+  pub output: Option<String>,
+  pub filename: Option<String>,
+  pub mime_type: Option<String>,
+  pub size: Option<usize>,
+  #[serde(skip)]
+  pub summary: Option<String>,
+}
+
+impl fmt::Display for Extracted {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match (&self.summary, &self.output) {
+      (Some(summary), Some(output)) => write!(f, "extracted {} to {}", summary, output),
+      _ => write!(f, "This is no file for chunk {}", self.chunk_type),
+    }
+  }
+}
+
+/// The chunks taken out by `remove` or `strip`
+#[derive(Serialize)]
+pub struct Removed {
+  pub removed: Vec<ChunkSummary>,
+  /// Unsafe-to-copy chunks left out because a critical chunk was removed
+  pub discarded: Vec<ChunkSummary>,
+  /// Whether nothing was written
+  pub dry_run: bool,
+}
+
+impl fmt::Display for Removed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.removed.is_empty() {
+      return write!(f, "nothing to remove");
+    }
+    let verb = match self.dry_run {
+      true => "would remove",
+      false => "removed",
+    };
+    let lines: Vec<String> = self
+      .removed
+      .iter()
+      .map(|chunk| format!("{} chunk {} of {} bytes", verb, chunk.chunk_type, chunk.length))
+      .chain(discarded_lines(&self.discarded, self.dry_run))
+      .collect();
+    write!(f, "{}", lines.join("\n"))
+  }
+}
+
+/// One damaged place found by `repair`
+#[derive(Serialize)]
+pub struct RepairInfo {
+  pub offset: usize,
+  pub index: usize,
+  pub chunk_type: Option<String>,
+  pub damage: &'static str,
+  /// Computed crc of a chunk with a crc mismatch
+  pub expected_crc: Option<u32>,
+  pub stored_crc: Option<u32>,
+  pub stored_length: Option<u32>,
+  pub actual_length: Option<u32>,
+  pub skipped: Option<usize>,
+  pub fixed: bool,
+  #[serde(skip)]
+  pub summary: String,
+}
+
+impl From<&Repair> for RepairInfo {
+  fn from(repair: &Repair) -> RepairInfo {
+    let mut info = RepairInfo {
+      offset: repair.offset,
+      index: repair.index,
+      chunk_type: repair.chunk_type.map(|chunk_type| chunk_type.to_string()),
+      damage: "",
+      expected_crc: None,
+      stored_crc: None,
+      stored_length: None,
+      actual_length: None,
+      skipped: None,
+      fixed: repair.fixed,
+      summary: repair.to_string(),
+    };
+    match repair.damage {
+      Damage::CrcMismatch(expected, stored) => {
+        info.damage = "crc_mismatch";
+        info.expected_crc = Some(expected);
+        info.stored_crc = Some(stored);
+      }
+      Damage::CorruptLength(stored, actual) => {
+        info.damage = "corrupt_length";
+        info.stored_length = Some(stored);
+        info.actual_length = Some(actual);
+      }
+      Damage::Skipped(len) => {
+        info.damage = "skipped";
+        info.skipped = Some(len);
+      }
+    }
+    info
+  }
+}
+
+/// The damage reported by `repair`
+#[derive(Serialize)]
+pub struct Repaired {
+  pub repairs: Vec<RepairInfo>,
+  /// Whether nothing was written
+  pub dry_run: bool,
+}
+
+impl fmt::Display for Repaired {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.repairs.is_empty() {
+      return write!(f, "nothing to repair");
+    }
+    let lines: Vec<&str> = self.repairs.iter().map(|repair| repair.summary.as_str()).collect();
+    write!(f, "{}", lines.join("\n"))?;
+    if self.dry_run {
+      write!(f, "\ndry run, the repaired file is not written")?;
+    }
+    Ok(())
+  }
+}
+
+/// One broken rule found by `validate`
+#[derive(Serialize)]
+pub struct ViolationInfo {
+  pub index: Option<usize>,
+  pub chunk_type: Option<String>,
+  pub severity: String,
+  pub rule: &'static str,
+  pub message: String,
+  #[serde(skip)]
+  pub summary: String,
+}
+
+impl From<&Violation> for ViolationInfo {
+  fn from(violation: &Violation) -> ViolationInfo {
+    ViolationInfo {
+      index: violation.index,
+      chunk_type: violation.chunk_type.map(|chunk_type| chunk_type.to_string()),
+      severity: violation.severity.to_string(),
+      rule: violation.rule.name(),
+      message: violation.rule.to_string(),
+      summary: violation.to_string(),
+    }
+  }
+}
+
+/// The result of `validate`
+#[derive(Serialize)]
+pub struct Validated {
+  /// Whether no rule is broken with error severity
+  pub valid: bool,
+  pub violations: Vec<ViolationInfo>,
+}
+
+impl Validated {
+  pub fn new(violations: &[Violation]) -> Validated {
+    Validated {
+      valid: violations.iter().all(|violation| violation.severity != Severity::Error),
+      violations: violations.iter().map(ViolationInfo::from).collect(),
+    }
+  }
+}
+
+impl fmt::Display for Validated {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.violations.is_empty() {
+      return write!(f, "the png is valid");
+    }
+    let lines: Vec<&str> = self.violations.iter().map(|violation| violation.summary.as_str()).collect();
+    write!(f, "{}", lines.join("\n"))
+  }
+}
+
+/// The files written by `keygen`
+#[derive(Serialize)]
+pub struct Generated {
+  pub identity_path: String,
+  pub public_key_path: String,
+  pub public_key: String,
+}
+
+impl fmt::Display for Generated {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "the public key is written to {}", self.public_key_path)
+  }
+}
+
+/// The outcome of a command on one of several files
+#[derive(Serialize)]
+pub struct FileReport {
+  pub path: String,
+  /// Exit code the command would have for this file alone
+  pub code: i32,
+  /// The report of the command, absent when it failed
+  pub report: Option<serde_json::Value>,
+  pub error: Option<String>,
+  #[serde(skip)]
+  summary: String,
+}
+
+impl FileReport {
+  pub fn new<T: Serialize + fmt::Display>(path: &Path, code: i32, report: &T) -> FileReport {
+    let summary = report.to_string();
+    FileReport {
+      path: path.display().to_string(),
+      code,
+      report: serde_json::to_value(report).ok(),
+      error: None,
+      summary: match summary.is_empty() {
+        true => "done".to_owned(),
+        false => summary,
+      },
+    }
+  }
+
+  pub fn failed(path: &Path, code: i32, error: &dyn std::error::Error) -> FileReport {
+    FileReport {
+      path: path.display().to_string(),
+      code,
+      report: None,
+      error: Some(error.to_string()),
+      summary: format!("error: {}", error),
+    }
+  }
+}
+
+/// The outcome of a command on several files
+#[derive(Serialize)]
+pub struct Batch {
+  pub files: Vec<FileReport>,
+  /// Number of files with a zero exit code
+  pub succeeded: usize,
+  pub failed: usize,
+}
+
+impl Batch {
+  pub fn new(files: Vec<FileReport>) -> Batch {
+    let succeeded = files.iter().filter(|file| file.code == SUCCESS).count();
+    Batch {
+      failed: files.len() - succeeded,
+      succeeded,
+      files,
+    }
+  }
+
+  /// Exit code of the whole batch, the code shared by all failed files or FAILURE
+  pub fn code(&self) -> i32 {
+    let mut codes = self.files.iter().map(|file| file.code).filter(|code| *code != SUCCESS);
+    match codes.next() {
+      None => SUCCESS,
+      Some(code) if codes.all(|other| other == code) => code,
+      Some(_) => FAILURE,
+    }
+  }
+}
+
+impl fmt::Display for Batch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for file in &self.files {
+      match file.summary.contains('\n') {
+        true => writeln!(f, "{}:\n  {}", file.path, file.summary.replace('\n', "\n  "))?,
+        false => writeln!(f, "{}: {}", file.path, file.summary)?,
+      }
+    }
+    write!(f, "{} files, {} failed", self.files.len(), self.failed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pngme::chunk_type::ChunkType;
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: &[u8]) -> Vec<u8> {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()).as_bytes()
+  }
+
+  #[test]
+  fn test_chunk_info_json() {
+    let bytes = chunk("ruSt", b"hello");
+    let info = ChunkInfo::new(1, 33, &ChunkRef::try_from(bytes.as_slice()).unwrap());
+    let json = serde_json::to_value(&info).unwrap();
+    assert_eq!(
+      json,
+      serde_json::json!({
+        "index": 1,
+        "offset": 33,
+        "chunk_type": "ruSt",
+        "name": null,
+        "section": null,
+        "length": 5,
+        "crc": info.crc,
+        "critical": false,
+        "public": false,
+        "reserved_bit_valid": true,
+        "safe_to_copy": true,
+        "content": { "kind": "message", "text": "hello" },
+      })
+    );
+  }
+
+  #[test]
+  fn test_content_json() {
+    let content = |chunk_type, data| {
+      let bytes = chunk(chunk_type, data);
+      serde_json::to_value(Content::from(&ChunkRef::try_from(bytes.as_slice()).unwrap())).unwrap()
+    };
+    assert_eq!(content("ruSt", &[0xff, 0]), serde_json::json!({ "kind": "binary", "size": 2, "base64": "/wA=" }));
+    assert_eq!(content("IDAT", b"x"), serde_json::json!({ "kind": "image_data" }));
+    assert_eq!(content("ruSt", b""), serde_json::json!({ "kind": "empty" }));
+    assert_eq!(content("tEXt", b"Title\0dice")["keyword"], "Title");
+    assert_eq!(content("tEXt", b"no separator")["kind"], "invalid");
+    assert_eq!(content("fdAT", &[0, 0, 0, 3, 1, 2]), serde_json::json!({ "kind": "frame_data", "sequence_number": 3, "size": 2 }));
+    assert_eq!(content("fdAT", &[0, 3])["kind"], "invalid");
+    assert_eq!(content("acTL", &[0, 0, 0, 2, 0, 0, 0, 0])["num_frames"], 2);
+    assert_eq!(content("IHDR", &[0, 0, 0, 1, 0, 0, 0, 2, 8, 2, 0, 0, 0])["height"], 2);
+  }
+
+  #[test]
+  fn test_printed_text() {
+    let chunks = [
+      chunk("IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]),
+      chunk("tEXt", b"Comment\0hi"),
+      chunk("IDAT", b"data"),
+      chunk("ruSt", b"  "),
+      chunk("ruSt", b"msg"),
+    ];
+    let printed = Printed {
+      chunks: chunks
+        .iter()
+        .enumerate()
+        .map(|(index, bytes)| ChunkInfo::new(index, 0, &ChunkRef::try_from(bytes.as_slice()).unwrap()))
+        .collect(),
+    };
+    assert_eq!(
+      printed.to_string(),
+      [
+        "the image is 2x1, 8 bit rgb",
+        "the chunk type is tEXt (textual data), Comment: hi",
+        "the chunk type is ruSt (private), the msg is msg",
+      ]
+      .join("\n")
+    );
+  }
+
+  #[test]
+  fn test_batch() {
+    let removed = Removed {
+      removed: Vec::new(),
+      discarded: Vec::new(),
+      dry_run: false,
+    };
+    let error: pngme::Error = "not a png".into();
+    let batch = Batch::new(vec![
+      FileReport::new(Path::new("a.png"), SUCCESS, &removed),
+      FileReport::failed(Path::new("b.png"), NOT_PNG, error.as_ref()),
+    ]);
+    assert_eq!(batch.to_string(), "a.png: nothing to remove\nb.png: error: not a png\n2 files, 1 failed");
+    assert_eq!(batch.code(), NOT_PNG);
+    let json = serde_json::to_value(&batch).unwrap();
+    assert_eq!(json["files"][0]["report"], serde_json::json!({ "removed": [], "discarded": [], "dry_run": false }));
+    assert_eq!(json["files"][1]["error"], "not a png");
+    assert_eq!(json["failed"], 1);
+  }
+}
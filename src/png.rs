@@ -1,30 +1,150 @@
-use crate::{chunk::Chunk};
-use std::{convert::TryFrom, error, fmt, result, path::PathBuf, fs};
+use crate::apng::{self, AnimationControl, ApngError, Frames};
+use crate::chunk::{Chunk, ChunkError, ChunkRef, ParseError, ParseMode};
+use crate::chunk_type::ChunkType;
+use crate::image_data::{self, ImageDataError, ImageDataOptions};
+use crate::image_header::{ColorType, ImageHeader, ImageHeaderError};
+use crate::strip::StripFilter;
+use crate::validation::{self, Violation};
+use std::{convert::TryFrom, error, fmt, io, result, path::PathBuf, fs, str::FromStr};
 
+/// Where a new chunk is placed in the chunk list of a png
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkPosition {
+  /// Right before the IEND chunk, or at the end if there is no IEND
+  #[default]
+  BeforeIend,
+  /// Right before the first IDAT chunk
+  BeforeIdat,
+  /// Right after the run of consecutive IDAT chunks starting at the first IDAT
+  AfterIdat,
+  /// At an explicit index in the chunk list
+  Index(usize),
+  /// Right before the fcTL chunk starting the frame of an animation with this index
+  BeforeFrame(usize),
+}
+
+impl ChunkPosition {
+  /// Whether a chunk belongs between `previous` and `next`, `index` being the index of `next`
+  /// and `frame` the number of fcTL chunks before it
+  pub fn matches(&self, index: usize, frame: usize, previous: Option<&ChunkType>, next: Option<&ChunkType>) -> bool {
+    let is = |chunk_type: Option<&ChunkType>, name: &[u8; 4]| {
+      chunk_type.is_some_and(|chunk_type| chunk_type.bytes() == *name)
+    };
+    match self {
+      ChunkPosition::BeforeIend => next.is_none() || is(next, b"IEND"),
+      ChunkPosition::BeforeIdat => is(next, b"IDAT"),
+      ChunkPosition::AfterIdat => is(previous, b"IDAT") && !is(next, b"IDAT"),
+      ChunkPosition::Index(idx) => *idx == index,
+      ChunkPosition::BeforeFrame(idx) => *idx == frame && is(next, b"fcTL"),
+    }
+  }
+
+  /// The error reported when a png with `len` chunks and `frames` frames has no room for this
+  /// position
+  pub fn not_found(&self, len: usize, frames: usize) -> PngError {
+    match self {
+      ChunkPosition::Index(idx) => PngError::IndexOutOfRange(*idx, len),
+      ChunkPosition::BeforeFrame(idx) => PngError::FrameOutOfRange(*idx, frames),
+      _ => PngError::ChunkNotFound("IDAT".to_owned()),
+    }
+  }
+}
+
+impl FromStr for ChunkPosition {
+  type Err = PngError;
+  fn from_str(str: &str) -> result::Result<ChunkPosition, PngError> {
+    match str {
+      "before-iend" => Ok(ChunkPosition::BeforeIend),
+      "before-idat" => Ok(ChunkPosition::BeforeIdat),
+      "after-idat" => Ok(ChunkPosition::AfterIdat),
+      _ if str.starts_with("before-frame-") => str["before-frame-".len()..]
+        .parse::<usize>()
+        .map(ChunkPosition::BeforeFrame)
+        .map_err(|_| PngError::InvalidPosition(str.to_owned())),
+      _ => str
+        .parse::<usize>()
+        .map(ChunkPosition::Index)
+        .map_err(|_| PngError::InvalidPosition(str.to_owned())),
+    }
+  }
+}
+
+/// A png parsed with [`Png::parse`] and the problems met along the way
+#[derive(Debug)]
+pub struct ParsedPng {
+  pub png: Png,
+  pub diagnostics: Vec<ParseError>,
+  /// Index in the file of each chunk of `png`, as malformed chunks are left out
+  pub indices: Vec<usize>,
+}
+
+/// A png borrowed with [`PngRef::parse_lenient`] and the problems met along the way
+#[derive(Debug)]
+pub struct ParsedPngRef<'a> {
+  pub png: PngRef<'a>,
+  pub diagnostics: Vec<ParseError>,
+  /// Index in the file of each chunk of `png`, as malformed chunks are left out
+  pub indices: Vec<usize>,
+}
+
+/// A png file: the signature and its chunks
 #[derive(Debug)]
 pub struct Png {
   header: [u8; 8],
   chunks: Vec<Chunk>,
+  /// Whether critical chunks were added, changed or removed since the png was read
+  critical_changed: bool,
 }
 
 impl Png {
+  /// The png signature
   pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
   pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
     Png {
       header: Png::STANDARD_HEADER,
       chunks,
+      critical_changed: false,
     }
   }
 
+  /// Reads and parses a png file
   pub fn from_file(file_path: PathBuf) -> result::Result<Png, PngError> {
     let bytes = fs::read(&file_path).map_err(|_| PngError::PngFileOpenFail(file_path))?;
     Png::try_from(bytes.as_slice())
   }
 
+  /// Parses a png, collecting malformed chunks as diagnostics in lenient mode
+  pub fn parse(bytes: &[u8], mode: ParseMode) -> result::Result<ParsedPng, PngError> {
+    let header: [u8; 8] = bytes
+      .get(..8)
+      .and_then(|header| header.try_into().ok())
+      .ok_or(PngError::HeaderInValid)?;
+    if header != Png::STANDARD_HEADER {
+      return Err(PngError::HeaderInValid);
+    }
+    let shift = |mut error: ParseError| {
+      error.offset += header.len();
+      error
+    };
+    let parsed = Chunk::parse_sequence(&bytes[8..], mode).map_err(|error| PngError::ChunksInvalid(shift(error)))?;
+    let diagnostics = parsed.diagnostics.into_iter().map(shift).collect();
+    let png = Png {
+      header,
+      chunks: parsed.chunks,
+      critical_changed: false,
+    };
+    Ok(ParsedPng {
+      png,
+      diagnostics,
+      indices: parsed.indices,
+    })
+  }
+
   pub fn chunks(&self) -> &Vec<Chunk> {
     &self.chunks
   }
 
+  /// First chunk of the given type
   pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
     self
       .chunks
@@ -32,18 +152,179 @@ impl Png {
       .find(|&x| x.chunk_type().to_string() == chunk_type)
   }
 
-  pub fn append_chunk(&mut self, chunk: Chunk) -> () {
+  /// The decoded IHDR chunk
+  pub fn image_header(&self) -> result::Result<ImageHeader, PngError> {
+    let chunk = self
+      .chunk_by_type("IHDR")
+      .ok_or(PngError::ChunkNotFound("IHDR".to_owned()))?;
+    ImageHeader::try_from(chunk).map_err(PngError::InvalidImageHeader)
+  }
+
+  /// Width and height of the image, if it has a valid IHDR chunk
+  pub fn dimensions(&self) -> Option<(u32, u32)> {
+    self
+      .image_header()
+      .ok()
+      .map(|header| (header.width, header.height))
+  }
+
+  /// Color type of the image, if it has a valid IHDR chunk
+  pub fn color_type(&self) -> Option<ColorType> {
+    self.image_header().ok().map(|header| header.color_type)
+  }
+
+  /// The decompressed and unfiltered scanlines of all IDAT chunks
+  ///
+  /// Scanlines are stored without their filter type byte, one Adam7 pass after another when the
+  /// image is interlaced.
+  pub fn image_data(&self) -> result::Result<Vec<u8>, PngError> {
+    let header = self.image_header()?;
+    let compressed: Vec<u8> = self
+      .chunks_by_type("IDAT")
+      .flat_map(|chunk| chunk.data().iter().copied())
+      .collect();
+    image_data::decode(&header, &compressed).map_err(PngError::InvalidImageData)
+  }
+
+  /// Filters and compresses raw scanlines into new IDAT chunks replacing the current ones
+  ///
+  /// The new chunks take the place of the first IDAT chunk, or go before IEND if there is none.
+  pub fn set_image_data(&mut self, raw: &[u8], options: &ImageDataOptions) -> result::Result<(), PngError> {
+    if options.chunk_size == 0 || options.chunk_size > Chunk::MAX_LENGTH as usize {
+      return Err(PngError::InvalidImageData(ImageDataError::InvalidChunkSize(options.chunk_size)));
+    }
+    let header = self.image_header()?;
+    let compressed = image_data::encode(&header, raw, options.compression_level).map_err(PngError::InvalidImageData)?;
+    let idx = self
+      .position_index(ChunkPosition::BeforeIdat)
+      .or_else(|_| self.position_index(ChunkPosition::BeforeIend))?;
+    let idat = ChunkType::from_str("IDAT").unwrap();
+    self.chunks.retain(|chunk| *chunk.chunk_type() != idat);
+    let chunks = compressed
+      .chunks(options.chunk_size)
+      .map(|data| Chunk::new(idat, data.to_vec()));
+    self.chunks.splice(idx..idx, chunks);
+    self.critical_changed = true;
+    Ok(())
+  }
+
+  /// The decoded acTL chunk, `None` when the png is not animated
+  pub fn animation_control(&self) -> Option<result::Result<AnimationControl, ApngError>> {
+    self.chunk_by_type("acTL").map(AnimationControl::try_from)
+  }
+
+  /// The frames of an animated png, in order
+  pub fn frames(&self) -> Frames<'_> {
+    apng::frames(&self.chunks)
+  }
+
+  /// Checks the png against the chunk ordering and multiplicity rules of the spec
+  pub fn validate(&self) -> Vec<Violation> {
+    let chunks: Vec<ChunkRef<'_>> = self.chunks.iter().map(ChunkRef::from).collect();
+    validation::validate(&chunks)
+  }
+
+  /// All chunks of the given type, in order
+  pub fn chunks_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a Chunk> + 'a {
+    self
+      .chunks
+      .iter()
+      .filter(move |x| x.chunk_type().to_string() == chunk_type)
+  }
+
+  /// Pushes a chunk at the very end, after IEND
+  pub fn append_chunk(&mut self, chunk: Chunk) {
+    self.critical_changed |= chunk.chunk_type().is_critical();
     self.chunks.push(chunk);
   }
 
+  /// Resolves a position to an index in the chunk list
+  pub fn position_index(&self, position: ChunkPosition) -> result::Result<usize, PngError> {
+    let is_frame = |chunk: &Chunk| chunk.chunk_type().bytes() == *b"fcTL";
+    let mut frame = 0;
+    for idx in 0..=self.chunks.len() {
+      let previous = idx.checked_sub(1).map(|idx| self.chunks[idx].chunk_type());
+      let next = self.chunks.get(idx).map(|chunk| chunk.chunk_type());
+      if position.matches(idx, frame, previous, next) {
+        return Ok(idx);
+      }
+      if self.chunks.get(idx).is_some_and(is_frame) {
+        frame += 1;
+      }
+    }
+    Err(position.not_found(self.chunks.len(), frame))
+  }
+
+  /// Inserts a chunk at the given position, returning the index it was placed at
+  pub fn insert_chunk(
+    &mut self,
+    chunk: Chunk,
+    position: ChunkPosition,
+  ) -> result::Result<usize, PngError> {
+    let idx = self.position_index(position)?;
+    self.critical_changed |= chunk.chunk_type().is_critical();
+    self.chunks.insert(idx, chunk);
+    Ok(idx)
+  }
+
+  /// Removes the first chunk of the given type
   pub fn remove_chunk(&mut self, chunk_type: &str) -> result::Result<Chunk, PngError> {
     let idx = self
       .chunks
       .iter()
       .position(|x| x.chunk_type().to_string() == chunk_type).ok_or(PngError::ChunkNotFound(chunk_type.to_owned()))?;
-    Ok(self.chunks.remove(idx))
+    let chunk = self.chunks.remove(idx);
+    self.critical_changed |= chunk.chunk_type().is_critical();
+    Ok(chunk)
+  }
+
+  /// Whether critical chunks were added, changed or removed since the png was read
+  pub fn is_critical_changed(&self) -> bool {
+    self.critical_changed
+  }
+
+  /// Records that critical chunks changed before the png was built, as when they were repaired
+  pub fn mark_critical_changed(&mut self) {
+    self.critical_changed = true;
+  }
+
+  /// Removes the chunks the png spec forbids copying once critical chunks changed and returns them
+  ///
+  /// These are the unknown ancillary chunks whose safe-to-copy bit is not set, as they may depend
+  /// on the critical chunks. Nothing is removed while the critical chunks are unchanged.
+  pub fn discard_unsafe(&mut self) -> Vec<Chunk> {
+    if !self.critical_changed {
+      return Vec::new();
+    }
+    let (kept, discarded) = self
+      .chunks
+      .drain(..)
+      .partition(|chunk| chunk.chunk_type().is_copy_allowed());
+    self.chunks = kept;
+    discarded
+  }
+
+  /// Removes every chunk selected by the filter and returns them, grouped by type
+  ///
+  /// Critical chunks are never removed.
+  pub fn strip(&mut self, filter: &StripFilter) -> Vec<Chunk> {
+    let mut chunk_types: Vec<ChunkType> = Vec::new();
+    for chunk in &self.chunks {
+      let chunk_type = chunk.chunk_type();
+      if filter.strips(chunk_type) && !chunk_types.contains(chunk_type) {
+        chunk_types.push(*chunk_type);
+      }
+    }
+    let mut removed = Vec::new();
+    for chunk_type in chunk_types {
+      while let Ok(chunk) = self.remove_chunk(&chunk_type.to_string()) {
+        removed.push(chunk);
+      }
+    }
+    removed
   }
 
+  /// The png serialized as a file
   pub fn as_bytes(&self) -> Vec<u8> {
     let chunk_bytes: Vec<u8> = self
       .chunks
@@ -66,13 +347,170 @@ impl fmt::Display for Png {
   }
 }
 
+/// A png borrowing its chunks from a buffer, for reading without copying
+///
+/// Chunk crcs are only checked for the chunks that are read, and [`PngRef::to_png`] copies all
+/// of them into an owned [`Png`] for editing.
+#[derive(Debug)]
+pub struct PngRef<'a> {
+  chunks: Vec<ChunkRef<'a>>,
+  offsets: Vec<usize>,
+}
+
+impl<'a> PngRef<'a> {
+  /// Borrows the chunks of a png, failing on a bad signature, chunk framing or chunk type
+  pub fn parse(bytes: &'a [u8]) -> result::Result<PngRef<'a>, PngError> {
+    if bytes.get(..8) != Some(&Png::STANDARD_HEADER[..]) {
+      return Err(PngError::HeaderInValid);
+    }
+    let mut png = PngRef {
+      chunks: Vec::new(),
+      offsets: Vec::new(),
+    };
+    for chunk in ChunkRef::sequence(&bytes[8..]) {
+      let (offset, chunk) = chunk.map_err(|mut error| {
+        error.offset += 8;
+        PngError::ChunksInvalid(error)
+      })?;
+      png.offsets.push(offset + 8);
+      png.chunks.push(chunk);
+    }
+    Ok(png)
+  }
+
+  /// Borrows the chunks of a png, collecting malformed chunks as diagnostics like
+  /// [`ParseMode::Lenient`]
+  ///
+  /// The crc of every chunk is checked, but no chunk is copied.
+  pub fn parse_lenient(bytes: &'a [u8]) -> result::Result<ParsedPngRef<'a>, PngError> {
+    if bytes.get(..8) != Some(&Png::STANDARD_HEADER[..]) {
+      return Err(PngError::HeaderInValid);
+    }
+    let mut parsed = ParsedPngRef {
+      png: PngRef {
+        chunks: Vec::new(),
+        offsets: Vec::new(),
+      },
+      diagnostics: Vec::new(),
+      indices: Vec::new(),
+    };
+    for (index, chunk) in ChunkRef::sequence(&bytes[8..]).enumerate() {
+      let chunk = chunk.and_then(|(offset, chunk)| {
+        chunk.verify_crc().map(|_| (offset, chunk)).map_err(|error| ParseError {
+          offset,
+          index,
+          chunk_type: Some(*chunk.chunk_type()),
+          error,
+        })
+      });
+      match chunk {
+        Ok((offset, chunk)) => {
+          parsed.png.offsets.push(offset + 8);
+          parsed.png.chunks.push(chunk);
+          parsed.indices.push(index);
+        }
+        Err(mut error) => {
+          error.offset += 8;
+          parsed.diagnostics.push(error);
+        }
+      }
+    }
+    Ok(parsed)
+  }
+
+  /// All chunks, whose crc may not be checked yet
+  pub fn chunks(&self) -> &[ChunkRef<'a>] {
+    &self.chunks
+  }
+
+  /// Byte offset of the chunk at `index` in the file
+  pub fn offset(&self, index: usize) -> usize {
+    self.offsets[index]
+  }
+
+  /// Locates an error found in the chunk at `index`
+  fn parse_error(&self, index: usize, error: ChunkError) -> PngError {
+    PngError::ChunksInvalid(ParseError {
+      offset: self.offsets[index],
+      index,
+      chunk_type: Some(*self.chunks[index].chunk_type()),
+      error,
+    })
+  }
+
+  /// The chunk at `index` once its crc is checked
+  pub fn verify(&self, index: usize) -> result::Result<ChunkRef<'a>, PngError> {
+    let chunk = self.chunks[index];
+    chunk.verify_crc().map_err(|error| self.parse_error(index, error))?;
+    Ok(chunk)
+  }
+
+  /// Checks the crc of every chunk
+  pub fn verify_all(&self) -> result::Result<(), PngError> {
+    (0..self.chunks.len()).try_for_each(|index| self.verify(index).map(|_| ()))
+  }
+
+  /// All chunks of the given type, in order, each checked as it is reached
+  pub fn chunks_by_type<'s>(
+    &'s self,
+    chunk_type: &'s str,
+  ) -> impl Iterator<Item = result::Result<ChunkRef<'a>, PngError>> + 's {
+    (0..self.chunks.len())
+      .filter(move |&index| self.chunks[index].chunk_type().to_string() == chunk_type)
+      .map(move |index| self.verify(index))
+  }
+
+  /// First chunk of the given type, checked
+  pub fn chunk_by_type(&self, chunk_type: &str) -> Option<result::Result<ChunkRef<'a>, PngError>> {
+    self.chunks_by_type(chunk_type).next()
+  }
+
+  /// The decoded IHDR chunk
+  pub fn image_header(&self) -> result::Result<ImageHeader, PngError> {
+    let index = self
+      .chunks
+      .iter()
+      .position(|chunk| chunk.chunk_type().bytes() == *b"IHDR")
+      .ok_or(PngError::ChunkNotFound("IHDR".to_owned()))?;
+    let chunk = self.chunks[index].to_chunk().map_err(|error| self.parse_error(index, error))?;
+    ImageHeader::try_from(&chunk).map_err(PngError::InvalidImageHeader)
+  }
+
+  /// The decompressed and unfiltered scanlines of all IDAT chunks, as [`Png::image_data`]
+  pub fn image_data(&self) -> result::Result<Vec<u8>, PngError> {
+    let header = self.image_header()?;
+    let mut compressed = Vec::new();
+    for chunk in self.chunks_by_type("IDAT") {
+      compressed.extend_from_slice(chunk?.data());
+    }
+    image_data::decode(&header, &compressed).map_err(PngError::InvalidImageData)
+  }
+
+  /// Copies the chunks into an owned png, checking all of their crcs
+  pub fn to_png(&self) -> result::Result<Png, PngError> {
+    let chunks = self
+      .chunks
+      .iter()
+      .enumerate()
+      .map(|(index, chunk)| chunk.to_chunk().map_err(|error| self.parse_error(index, error)))
+      .collect::<result::Result<_, _>>()?;
+    Ok(Png::from_chunks(chunks))
+  }
+}
 
+/// Png errors
 #[derive(Debug)]
 pub enum PngError {
-  ChunksInvalid,
+  ChunksInvalid(ParseError),
   HeaderInValid,
   ChunkNotFound(String),
   PngFileOpenFail(PathBuf),
+  InvalidPosition(String),
+  IndexOutOfRange(usize, usize),
+  FrameOutOfRange(usize, usize),
+  Io(io::Error),
+  InvalidImageHeader(ImageHeaderError),
+  InvalidImageData(ImageDataError),
 }
 
 impl error::Error for PngError {}
@@ -80,28 +518,42 @@ impl error::Error for PngError {}
 impl fmt::Display for PngError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
-      PngError::ChunksInvalid => write!(f, "Invalid ChunksBytes",),
+      PngError::ChunksInvalid(error) => write!(f, "Invalid ChunksBytes: {}", error),
       PngError::HeaderInValid => write!(f, "Invalid header bytes",),
       PngError::ChunkNotFound(chunk_type) => write!(f, "The chunk {} is not found", chunk_type),
       PngError::PngFileOpenFail(file_path) => write!(f, "It's fail to open {:?}", file_path.to_str()),
+      PngError::InvalidPosition(position) => write!(
+        f,
+        "Invalid chunk position {}, expected before-iend, before-idat, after-idat, before-frame-N or an index",
+        position
+      ),
+      PngError::IndexOutOfRange(idx, len) => write!(
+        f,
+        "Chunk index {} is out of range for a png with {} chunks",
+        idx, len
+      ),
+      PngError::FrameOutOfRange(idx, frames) => write!(
+        f,
+        "Frame {} is out of range for a png with {} frames",
+        idx, frames
+      ),
+      PngError::Io(error) => write!(f, "IO error: {}", error),
+      PngError::InvalidImageHeader(error) => write!(f, "Invalid IHDR chunk: {}", error),
+      PngError::InvalidImageData(error) => write!(f, "Invalid image data: {}", error),
     }
   }
 }
 
+impl From<io::Error> for PngError {
+  fn from(error: io::Error) -> PngError {
+    PngError::Io(error)
+  }
+}
+
 impl TryFrom<&[u8]> for Png {
   type Error = PngError;
   fn try_from(bytes: &[u8]) -> result::Result<Png, PngError> {
-    let (header, chunks_bytes) = bytes.split_at(8);
-    let header = header.try_into().unwrap();
-    if header != Png::STANDARD_HEADER {
-      return Err(PngError::HeaderInValid);
-    }
-    match Chunk::try_from_sequence(chunks_bytes) {
-      Ok(chunks) => Ok(Png { header, chunks }),
-      Err(x) => {
-        Err(PngError::ChunksInvalid)
-      }
-    }
+    Png::parse(bytes, ParseMode::Strict).map(|parsed| parsed.png)
   }
 }
 
@@ -114,13 +566,11 @@ mod tests {
   use std::convert::TryFrom;
 
   fn testing_chunks() -> Vec<Chunk> {
-    let mut chunks = Vec::new();
-
-    chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-    chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-    chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-    chunks
+    vec![
+      chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+      chunk_from_strings("miDl", "I am another chunk").unwrap(),
+      chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+    ]
   }
 
   fn testing_png() -> Png {
@@ -203,6 +653,30 @@ mod tests {
     assert!(png.is_err());
   }
 
+  #[test]
+  fn test_truncated_png() {
+    for len in 0..PNG_FILE.len() {
+      let _ = Png::try_from(&PNG_FILE[..len]);
+    }
+    assert!(Png::try_from(&PNG_FILE[..7]).is_err());
+    match Png::try_from(&PNG_FILE[..PNG_FILE.len() - 1]) {
+      Err(PngError::ChunksInvalid(error)) => {
+        assert_eq!(error.offset, PNG_FILE.len() - 12);
+        assert_eq!(error.chunk_type.unwrap().to_string(), "IEND");
+      }
+      _ => panic!("expected a chunk parse error"),
+    }
+  }
+
+  #[test]
+  fn test_lenient_png() {
+    let ParsedPng { png, diagnostics, indices } = Png::parse(&PNG_FILE[..PNG_FILE.len() - 1], ParseMode::Lenient).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(indices, (0..png.chunks().len()).collect::<Vec<_>>());
+    assert!(png.chunk_by_type("IDAT").is_some());
+    assert!(png.chunk_by_type("IEND").is_none());
+  }
+
   #[test]
   fn test_list_chunks() {
     let png = testing_png();
@@ -218,6 +692,13 @@ mod tests {
     assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
   }
 
+  #[test]
+  fn test_chunks_by_type() {
+    let png = Png::try_from(&PNG_FILE[..]).unwrap();
+    assert_eq!(png.chunks_by_type("IDAT").count(), 1);
+    assert_eq!(png.chunks_by_type("teSt").count(), 0);
+  }
+
   #[test]
   fn test_append_chunk() {
     let mut png = testing_png();
@@ -227,6 +708,90 @@ mod tests {
     assert_eq!(&chunk.data_as_string().unwrap(), "Message");
   }
 
+  #[test]
+  fn test_insert_chunk_before_iend() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let idx = png
+      .insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::default())
+      .unwrap();
+    let chunks = png.chunks();
+    assert_eq!(idx, chunks.len() - 2);
+    assert_eq!(&chunks[idx].chunk_type().to_string(), "TeSt");
+    assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+  }
+
+  #[test]
+  fn test_insert_chunk_around_idat() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let before = png
+      .insert_chunk(chunk_from_strings("BeFo", "Message").unwrap(), ChunkPosition::BeforeIdat)
+      .unwrap();
+    assert_eq!(&png.chunks()[before + 1].chunk_type().to_string(), "IDAT");
+    let after = png
+      .insert_chunk(chunk_from_strings("AfTe", "Message").unwrap(), ChunkPosition::AfterIdat)
+      .unwrap();
+    assert_eq!(&png.chunks()[after - 1].chunk_type().to_string(), "IDAT");
+  }
+
+  #[test]
+  fn test_insert_chunk_out_of_range() {
+    let mut png = testing_png();
+    let result = png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::Index(4));
+    assert!(result.is_err());
+    let result = png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::BeforeIdat);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn test_chunk_position_from_str() {
+    assert_eq!(ChunkPosition::from_str("before-iend").unwrap(), ChunkPosition::BeforeIend);
+    assert_eq!(ChunkPosition::from_str("after-idat").unwrap(), ChunkPosition::AfterIdat);
+    assert_eq!(ChunkPosition::from_str("3").unwrap(), ChunkPosition::Index(3));
+    assert_eq!(ChunkPosition::from_str("before-frame-2").unwrap(), ChunkPosition::BeforeFrame(2));
+    assert!(ChunkPosition::from_str("middle").is_err());
+    assert!(ChunkPosition::from_str("before-frame-x").is_err());
+  }
+
+  #[test]
+  fn test_frames() {
+    let frame = |sequence_number| apng::FrameControl {
+      sequence_number,
+      width: 1,
+      height: 1,
+      x_offset: 0,
+      y_offset: 0,
+      delay_num: 1,
+      delay_den: 2,
+      dispose_op: apng::DisposeOp::Background,
+      blend_op: apng::BlendOp::Over,
+    };
+    let mut frame_data = 2u32.to_be_bytes().to_vec();
+    frame_data.extend(b"data");
+    let mut png = Png::from_chunks(vec![
+      Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]),
+      AnimationControl { num_frames: 2, num_plays: 1 }.to_chunk(),
+      frame(0).to_chunk(),
+      Chunk::new(ChunkType::from_str("IDAT").unwrap(), b"image".to_vec()),
+      frame(1).to_chunk(),
+      Chunk::new(ChunkType::from_str("fdAT").unwrap(), frame_data),
+      Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+    ]);
+    assert_eq!(png.animation_control().unwrap().unwrap().num_frames, 2);
+    let delays: Vec<_> = png.frames().map(|frame| frame.unwrap().control.delay()).collect();
+    assert_eq!(delays, [std::time::Duration::from_millis(500); 2]);
+    assert!(png.validate().is_empty());
+    let idx = png
+      .insert_chunk(chunk_from_strings("ruSt", "Message").unwrap(), ChunkPosition::BeforeFrame(1))
+      .unwrap();
+    assert_eq!(idx, 4);
+    let frames: Vec<_> = png.frames().map(|frame| frame.unwrap().index).collect();
+    assert_eq!(frames, [2, 5]);
+    assert!(matches!(
+      png.position_index(ChunkPosition::BeforeFrame(2)),
+      Err(PngError::FrameOutOfRange(2, 2))
+    ));
+  }
+
   #[test]
   fn test_remove_chunk() {
     let mut png = testing_png();
@@ -236,6 +801,144 @@ mod tests {
     assert!(chunk.is_none());
   }
 
+  #[test]
+  fn test_strip() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    png.insert_chunk(chunk_from_strings("tEXt", "Comment\0hi").unwrap(), ChunkPosition::BeforeIend).unwrap();
+    png.insert_chunk(chunk_from_strings("ruSt", "private").unwrap(), ChunkPosition::BeforeIdat).unwrap();
+    png.insert_chunk(chunk_from_strings("tEXt", "Title\0dice").unwrap(), ChunkPosition::BeforeIdat).unwrap();
+    let critical = png.chunks().iter().filter(|chunk| chunk.chunk_type().is_critical()).count();
+    let removed: Vec<String> = png
+      .strip(&StripFilter::default())
+      .iter()
+      .map(|chunk| chunk.chunk_type().to_string())
+      .collect();
+    assert_eq!(removed, ["sRGB", "gAMA", "pHYs", "ruSt", "tEXt", "tEXt"]);
+    assert_eq!(png.chunks().len(), critical);
+    assert_eq!(png.image_data().unwrap().len(), 50 * 50 * 4);
+  }
+
+  #[test]
+  fn test_image_header() {
+    let png = Png::try_from(&PNG_FILE[..]).unwrap();
+    assert_eq!(png.dimensions(), Some((50, 50)));
+    assert_eq!(png.color_type(), Some(ColorType::Rgba));
+    assert_eq!(png.image_header().unwrap().bit_depth, 8);
+    assert!(testing_png().image_header().is_err());
+  }
+
+  #[test]
+  fn test_image_data() {
+    let png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let raw = png.image_data().unwrap();
+    assert_eq!(raw.len(), 50 * 50 * 4);
+  }
+
+  #[test]
+  fn test_discard_unsafe() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    png.insert_chunk(chunk_from_strings("ruST", "unsafe").unwrap(), ChunkPosition::BeforeIend).unwrap();
+    png.insert_chunk(chunk_from_strings("ruSt", "safe").unwrap(), ChunkPosition::BeforeIend).unwrap();
+    assert!(!png.is_critical_changed());
+    assert!(png.discard_unsafe().is_empty());
+    let raw = png.image_data().unwrap();
+    png.set_image_data(&raw, &ImageDataOptions::default()).unwrap();
+    assert!(png.is_critical_changed());
+    let discarded = png.discard_unsafe();
+    assert_eq!(discarded.len(), 1);
+    assert_eq!(discarded[0].chunk_type().to_string(), "ruST");
+    assert!(png.chunk_by_type("ruSt").is_some());
+    assert!(png.chunk_by_type("IHDR").is_some());
+  }
+
+  #[test]
+  fn test_png_ref() {
+    let png = PngRef::parse(&PNG_FILE).unwrap();
+    assert_eq!(png.offset(0), 8);
+    assert_eq!(png.image_header().unwrap().width, 50);
+    assert_eq!(png.image_data().unwrap().len(), 50 * 50 * 4);
+    assert_eq!(png.to_png().unwrap().as_bytes(), PNG_FILE.to_vec());
+
+    let mut bytes = PNG_FILE.to_vec();
+    let index = png.chunks().iter().position(|chunk| chunk.chunk_type().bytes() == *b"IDAT").unwrap();
+    let crc_offset = png.offset(index) + 8 + png.chunks()[index].length() as usize;
+    bytes[crc_offset] ^= 0xff;
+    let png = PngRef::parse(&bytes).unwrap();
+    assert!(png.chunk_by_type("IHDR").unwrap().is_ok());
+    assert!(png.image_header().is_ok());
+    assert!(png.chunk_by_type("IDAT").unwrap().is_err());
+    assert!(png.verify_all().is_err());
+    assert!(matches!(png.to_png(), Err(PngError::ChunksInvalid(error)) if error.index == index));
+    assert!(matches!(PngRef::parse(&bytes[1..]), Err(PngError::HeaderInValid)));
+  }
+
+  #[test]
+  fn test_png_ref_lenient() {
+    let png = PngRef::parse(&PNG_FILE).unwrap();
+    let index = png.chunks().iter().position(|chunk| chunk.chunk_type().bytes() == *b"IDAT").unwrap();
+    let crc_offset = png.offset(index) + 8 + png.chunks()[index].length() as usize;
+    let mut bytes = PNG_FILE.to_vec();
+    bytes[crc_offset] ^= 0xff;
+    let parsed = PngRef::parse_lenient(&bytes).unwrap();
+    assert_eq!(parsed.diagnostics.len(), 1);
+    assert_eq!(parsed.diagnostics[0].index, index);
+    assert_eq!(parsed.diagnostics[0].offset, png.offset(index));
+    assert!(parsed.png.chunk_by_type("IDAT").is_none());
+    assert!(!parsed.indices.contains(&index));
+    assert_eq!(parsed.png.chunks().len(), png.chunks().len() - 1);
+  }
+
+  #[test]
+  fn test_set_image_data() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let violations = png.validate();
+    let idat_idx = png.position_index(ChunkPosition::BeforeIdat).unwrap();
+    let mut raw = png.image_data().unwrap();
+    raw[0] ^= 0xff;
+    let options = ImageDataOptions {
+      chunk_size: 1000,
+      compression_level: 9,
+    };
+    png.set_image_data(&raw, &options).unwrap();
+    let idats: Vec<&Chunk> = png.chunks_by_type("IDAT").collect();
+    assert!(idats.len() > 1);
+    assert!(idats.iter().all(|chunk| chunk.length() <= 1000));
+    assert!(png.chunks()[idat_idx..idat_idx + idats.len()]
+      .iter()
+      .all(|chunk| chunk.chunk_type().to_string() == "IDAT"));
+    assert_eq!(png.image_data().unwrap(), raw);
+    assert_eq!(png.validate().len(), violations.len());
+  }
+
+  #[test]
+  fn test_set_image_data_invalid() {
+    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let raw = png.image_data().unwrap();
+    let options = ImageDataOptions {
+      chunk_size: 0,
+      ..ImageDataOptions::default()
+    };
+    assert!(matches!(
+      png.set_image_data(&raw, &options),
+      Err(PngError::InvalidImageData(ImageDataError::InvalidChunkSize(0)))
+    ));
+    assert!(matches!(
+      png.set_image_data(&raw[1..], &ImageDataOptions::default()),
+      Err(PngError::InvalidImageData(ImageDataError::InvalidLength(_, _)))
+    ));
+    assert!(testing_png().image_data().is_err());
+  }
+
+  #[test]
+  fn test_validate() {
+    let png = Png::try_from(&PNG_FILE[..]).unwrap();
+    let violations = png.validate();
+    // the sample file carries a private critical RuSt chunk
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].to_string(), "chunk 5 (RuSt): error: unknown critical chunk");
+    assert_eq!(testing_png().validate().len(), 5);
+  }
+
   #[test]
   fn test_png_from_image_file() {
     let png = Png::try_from(&PNG_FILE[..]);
@@ -246,7 +949,7 @@ mod tests {
   fn test_as_bytes() {
     let png = Png::try_from(&PNG_FILE[..]).unwrap();
     let actual = png.as_bytes();
-    let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+    let expected: Vec<u8> = PNG_FILE.to_vec();
     assert_eq!(actual, expected);
   }
 
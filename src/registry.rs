@@ -0,0 +1,168 @@
+//! The chunk types defined by the png spec and its registered extensions
+//!
+//! Each [`Definition`] gives the name of a chunk type, where it is specified, how many chunks of
+//! that type a png may hold and where they must appear. [`crate::validation`] checks pngs against
+//! these definitions and `print` uses them to describe chunks. The APNG chunk types have their
+//! private bit set, but are part of the spec all the same.
+
+use crate::chunk_type::ChunkType;
+
+/// How many chunks of a type a png may hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplicity {
+  /// Exactly one
+  One,
+  /// At least one
+  OneOrMore,
+  /// None or one
+  AtMostOne,
+  /// Any number
+  Any,
+}
+
+impl Multiplicity {
+  /// Whether a png must hold a chunk of the type
+  pub fn is_required(&self) -> bool {
+    matches!(self, Multiplicity::One | Multiplicity::OneOrMore)
+  }
+
+  /// Whether a png may hold at most one chunk of the type
+  pub fn is_unique(&self) -> bool {
+    matches!(self, Multiplicity::One | Multiplicity::AtMostOne)
+  }
+}
+
+/// Where chunks of a type must appear in a png
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ordering {
+  /// The first chunk
+  First,
+  /// Before PLTE and IDAT
+  BeforePalette,
+  /// After PLTE and before IDAT
+  AfterPalette,
+  /// Before IDAT
+  BeforeImageData,
+  /// In a single run of consecutive chunks
+  Consecutive,
+  /// The last chunk
+  Last,
+  /// Anywhere between IHDR and IEND
+  Anywhere,
+}
+
+/// A standard or registered chunk type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Definition {
+  pub chunk_type: &'static str,
+  /// What the chunk holds, in the words of the spec
+  pub name: &'static str,
+  /// The section of the png spec, or the extensions document, which defines the chunk
+  pub section: &'static str,
+  pub multiplicity: Multiplicity,
+  pub ordering: Ordering,
+}
+
+impl Definition {
+  /// Whether decoders must understand the chunk, as given by the case of its first letter
+  pub fn is_critical(&self) -> bool {
+    self.chunk_type.as_bytes()[0].is_ascii_uppercase()
+  }
+}
+
+const fn define(
+  chunk_type: &'static str,
+  name: &'static str,
+  section: &'static str,
+  multiplicity: Multiplicity,
+  ordering: Ordering,
+) -> Definition {
+  Definition {
+    chunk_type,
+    name,
+    section,
+    multiplicity,
+    ordering,
+  }
+}
+
+const EXTENSIONS: &str = "PNG extensions";
+
+/// All known chunk types, critical chunks first
+pub const DEFINITIONS: [Definition; 32] = {
+  use Multiplicity::*;
+  use Ordering::*;
+  [
+    define("IHDR", "image header", "11.2.2", One, First),
+    define("PLTE", "palette", "11.2.3", AtMostOne, BeforeImageData),
+    define("IDAT", "image data", "11.2.4", OneOrMore, Consecutive),
+    define("IEND", "image trailer", "11.2.5", One, Last),
+    define("tRNS", "transparency", "11.3.1.1", AtMostOne, AfterPalette),
+    define("cHRM", "primary chromaticities and white point", "11.3.2.1", AtMostOne, BeforePalette),
+    define("gAMA", "image gamma", "11.3.2.2", AtMostOne, BeforePalette),
+    define("iCCP", "embedded ICC profile", "11.3.2.3", AtMostOne, BeforePalette),
+    define("sBIT", "significant bits", "11.3.2.4", AtMostOne, BeforePalette),
+    define("sRGB", "standard RGB color space", "11.3.2.5", AtMostOne, BeforePalette),
+    define("cICP", "coding-independent code points", "11.3.2.6", AtMostOne, BeforePalette),
+    define("mDCV", "mastering display color volume", "11.3.2.7", AtMostOne, BeforePalette),
+    define("cLLI", "content light level information", "11.3.2.8", AtMostOne, BeforePalette),
+    define("tEXt", "textual data", "11.3.3.3", Any, Anywhere),
+    define("zTXt", "compressed textual data", "11.3.3.4", Any, Anywhere),
+    define("iTXt", "international textual data", "11.3.3.5", Any, Anywhere),
+    define("bKGD", "background color", "11.3.4.1", AtMostOne, AfterPalette),
+    define("hIST", "image histogram", "11.3.4.2", AtMostOne, AfterPalette),
+    define("pHYs", "physical pixel dimensions", "11.3.4.3", AtMostOne, BeforeImageData),
+    define("sPLT", "suggested palette", "11.3.4.4", Any, BeforeImageData),
+    define("eXIf", "exchangeable image file profile", "11.3.4.5", AtMostOne, BeforeImageData),
+    define("tIME", "image last-modification time", "11.3.5.1", AtMostOne, Anywhere),
+    define("acTL", "animation control", "11.3.6.1", AtMostOne, BeforeImageData),
+    define("fcTL", "frame control", "11.3.6.2", Any, Anywhere),
+    define("fdAT", "frame data", "11.3.6.3", Any, Anywhere),
+    define("oFFs", "image offset", EXTENSIONS, AtMostOne, BeforeImageData),
+    define("pCAL", "calibration of pixel values", EXTENSIONS, AtMostOne, BeforeImageData),
+    define("sCAL", "physical scale of image subject", EXTENSIONS, AtMostOne, BeforeImageData),
+    define("sTER", "indicator of stereo image", EXTENSIONS, AtMostOne, BeforeImageData),
+    define("gIFg", "GIF graphic control extension", EXTENSIONS, Any, Anywhere),
+    define("gIFx", "GIF application extension", EXTENSIONS, Any, Anywhere),
+    define("dSIG", "digital signature", EXTENSIONS, Any, Anywhere),
+  ]
+};
+
+/// The definition of a chunk type, `None` for private and unknown chunk types
+pub fn lookup(chunk_type: &ChunkType) -> Option<&'static Definition> {
+  DEFINITIONS
+    .iter()
+    .find(|definition| definition.chunk_type.as_bytes() == chunk_type.bytes())
+}
+
+/// The definition of a chunk type given by name
+pub fn find(chunk_type: &str) -> Option<&'static Definition> {
+  DEFINITIONS.iter().find(|definition| definition.chunk_type == chunk_type)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::str::FromStr;
+
+  #[test]
+  fn test_definitions() {
+    for (index, definition) in DEFINITIONS.iter().enumerate() {
+      let chunk_type = ChunkType::from_str(definition.chunk_type).unwrap();
+      assert!(chunk_type.is_valid(), "{}", definition.chunk_type);
+      assert_eq!(definition.is_critical(), chunk_type.is_critical());
+      assert_eq!(definition.is_critical(), index < 4);
+      assert_eq!(find(definition.chunk_type), Some(definition));
+    }
+  }
+
+  #[test]
+  fn test_lookup() {
+    let definition = ChunkType::from_str("gAMA").unwrap().definition().unwrap();
+    assert_eq!(definition.name, "image gamma");
+    assert_eq!(definition.multiplicity, Multiplicity::AtMostOne);
+    assert!(ChunkType::from_str("ruSt").unwrap().definition().is_none());
+    assert!(ChunkType::from_str("GAMA").unwrap().definition().is_none());
+    assert!(find("IDAT").unwrap().multiplicity.is_required());
+  }
+}
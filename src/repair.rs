@@ -0,0 +1,355 @@
+//! Recovering the chunks of a damaged png
+//!
+//! Chunks whose crc does not match are reported and either kept with a recomputed crc or dropped.
+//! When a length field is corrupted, the length is recovered by looking for the stored crc after
+//! the chunk type, and otherwise the bytes up to the next chunk with a valid type and crc are
+//! skipped. These searches hash at most [`SCAN_BUDGET`] times the size of the input in total, so
+//! inputs crafted with many plausible chunk headers cannot make them quadratic.
+
+use std::{error, fmt};
+
+use crate::chunk::{Chunk, CRC, CRC_LEN, HEADER_LEN};
+use crate::chunk_type::ChunkType;
+
+/// How many bytes the length recovery and resync searches may hash per input byte
+const SCAN_BUDGET: usize = 16;
+
+/// Repair errors
+#[derive(Debug)]
+pub enum RepairError {
+  /// A critical chunk has a crc mismatch and would be dropped: index, chunk type
+  CriticalCrcMismatch(usize, ChunkType),
+}
+
+impl error::Error for RepairError {}
+
+impl fmt::Display for RepairError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RepairError::CriticalCrcMismatch(index, chunk_type) => write!(
+        f,
+        "Chunk {} ({}) is critical and has a crc mismatch, use --fix-crc to keep it",
+        index, chunk_type
+      ),
+    }
+  }
+}
+
+/// What was wrong at some place of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+  /// The crc does not match the chunk: computed, stored
+  CrcMismatch(u32, u32),
+  /// The length field is wrong: stored, actual
+  CorruptLength(u32, u32),
+  /// This many bytes were unreadable and skipped
+  Skipped(usize),
+}
+
+/// A damaged place of the input and whether it was fixed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repair {
+  /// Byte offset in the input
+  pub offset: usize,
+  /// Index of the damaged chunk among the chunks read so far
+  pub index: usize,
+  pub chunk_type: Option<ChunkType>,
+  pub damage: Damage,
+  /// Whether the chunk is kept in the repaired output
+  pub fixed: bool,
+}
+
+impl Repair {
+  /// Whether the damage may have changed a critical chunk, which skipped bytes could have held
+  pub fn is_critical(&self) -> bool {
+    self.chunk_type.is_none_or(|chunk_type| chunk_type.is_critical())
+  }
+}
+
+impl fmt::Display for Repair {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "offset {}: ", self.offset)?;
+    match (self.damage, self.chunk_type) {
+      (Damage::Skipped(len), _) => return write!(f, "skipped {} unreadable bytes", len),
+      (_, Some(chunk_type)) => write!(f, "chunk {} ({}): ", self.index, chunk_type)?,
+      (_, None) => write!(f, "chunk {}: ", self.index)?,
+    }
+    match self.damage {
+      Damage::CrcMismatch(expected, stored) => {
+        write!(f, "crc mismatch, expected {:08x} but stored {:08x}", expected, stored)?
+      }
+      Damage::CorruptLength(stored, actual) => {
+        write!(f, "corrupted length {}, actual length {}", stored, actual)?
+      }
+      Damage::Skipped(_) => {}
+    }
+    match self.fixed {
+      true => write!(f, ", fixed"),
+      false => write!(f, ", dropped"),
+    }
+  }
+}
+
+/// The chunks recovered from a damaged sequence with the repairs made
+#[derive(Debug, Default)]
+pub struct Repaired {
+  pub chunks: Vec<Chunk>,
+  pub repairs: Vec<Repair>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+  let field: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+  Some(u32::from_be_bytes(field))
+}
+
+fn chunk_type_at(bytes: &[u8], offset: usize) -> Option<ChunkType> {
+  let type_bytes: [u8; 4] = bytes.get(offset + 4..offset + HEADER_LEN)?.try_into().ok()?;
+  ChunkType::try_from(type_bytes).ok()
+}
+
+/// Data length of the chunk at `offset` when its length field fits in the input
+fn length_at(bytes: &[u8], offset: usize) -> Option<usize> {
+  let length = read_u32(bytes, offset)?;
+  let frame_len = HEADER_LEN + length as usize + CRC_LEN;
+  (length <= Chunk::MAX_LENGTH && bytes.len() - offset >= frame_len).then_some(length as usize)
+}
+
+/// Whether a chunk with a plausible header starts at `offset`, or the input ends there
+fn plausible_at(bytes: &[u8], offset: usize) -> bool {
+  let length = read_u32(bytes, offset);
+  offset == bytes.len()
+    || (chunk_type_at(bytes, offset).is_some() && length.is_some_and(|length| length <= Chunk::MAX_LENGTH))
+}
+
+/// The chunk at `offset` when its type, length and crc are all valid
+fn valid_at(bytes: &[u8], offset: usize) -> Option<Chunk> {
+  let chunk_type = chunk_type_at(bytes, offset)?;
+  let length = length_at(bytes, offset)?;
+  let data = &bytes[offset + HEADER_LEN..offset + HEADER_LEN + length];
+  let crc = read_u32(bytes, offset + HEADER_LEN + length)?;
+  (Chunk::create_crc(&chunk_type, data) == crc).then(|| Chunk::new(chunk_type, data.to_vec()))
+}
+
+/// Finds the data length of the chunk at `offset` from its crc, ignoring its length field
+fn recover_length(bytes: &[u8], offset: usize, chunk_type: &ChunkType, budget: &mut usize) -> Option<usize> {
+  let mut digest = CRC.digest();
+  digest.update(&chunk_type.bytes());
+  let data = &bytes[offset + HEADER_LEN..];
+  for length in 0..data.len().saturating_sub(CRC_LEN) + 1 {
+    if read_u32(data, length) == Some(digest.clone().finalize()) {
+      return Some(length);
+    }
+    if let Some(byte) = data.get(length) {
+      *budget = budget.checked_sub(1)?;
+      digest.update(std::slice::from_ref(byte));
+    }
+  }
+  None
+}
+
+/// Offset of the next valid chunk after `offset`, or the end of the input
+///
+/// Only offsets holding a valid chunk type and a length that fits in the input are hashed, and
+/// the search gives up once the chunks it checked exceed `budget`.
+fn resync(bytes: &[u8], offset: usize, budget: &mut usize) -> usize {
+  for next in offset + 1..bytes.len() {
+    if !chunk_type_at(bytes, next).is_some_and(|chunk_type| chunk_type.is_valid()) {
+      continue;
+    }
+    let Some(length) = length_at(bytes, next) else {
+      continue;
+    };
+    let Some(rest) = budget.checked_sub(length) else {
+      break;
+    };
+    *budget = rest;
+    if valid_at(bytes, next).is_some() {
+      return next;
+    }
+  }
+  bytes.len()
+}
+
+/// Recovers the chunks of a possibly damaged chunk sequence
+///
+/// Chunks with a crc mismatch are kept with a recomputed crc when `fix_crc` is set.
+pub fn repair(bytes: &[u8], fix_crc: bool) -> Repaired {
+  let mut repaired = Repaired::default();
+  let mut offset = 0usize;
+  let mut index = 0usize;
+  let mut budget = bytes.len().saturating_mul(SCAN_BUDGET);
+  while offset < bytes.len() {
+    let chunk_type = chunk_type_at(bytes, offset);
+    let damaged = |damage, fixed| Repair {
+      offset,
+      index,
+      chunk_type,
+      damage,
+      fixed,
+    };
+    if let Some(chunk) = valid_at(bytes, offset) {
+      offset += HEADER_LEN + chunk.length() as usize + CRC_LEN;
+      repaired.chunks.push(chunk);
+      index += 1;
+      continue;
+    }
+    if let Some(chunk_type) = chunk_type {
+      if let Some(length) = length_at(bytes, offset) {
+        let end = offset + HEADER_LEN + length + CRC_LEN;
+        if plausible_at(bytes, end) {
+          let data = &bytes[offset + HEADER_LEN..offset + HEADER_LEN + length];
+          let stored = read_u32(bytes, end - CRC_LEN).unwrap_or_default();
+          let expected = Chunk::create_crc(&chunk_type, data);
+          repaired.repairs.push(damaged(Damage::CrcMismatch(expected, stored), fix_crc));
+          if fix_crc {
+            repaired.chunks.push(Chunk::new(chunk_type, data.to_vec()));
+          }
+          offset = end;
+          index += 1;
+          continue;
+        }
+      }
+      if let Some(length) = recover_length(bytes, offset, &chunk_type, &mut budget) {
+        let stored = read_u32(bytes, offset).unwrap_or_default();
+        repaired.repairs.push(damaged(Damage::CorruptLength(stored, length as u32), true));
+        let data = &bytes[offset + HEADER_LEN..offset + HEADER_LEN + length];
+        repaired.chunks.push(Chunk::new(chunk_type, data.to_vec()));
+        offset += HEADER_LEN + length + CRC_LEN;
+        index += 1;
+        continue;
+      }
+    }
+    let next = resync(bytes, offset, &mut budget);
+    repaired.repairs.push(Repair {
+      chunk_type: None,
+      ..damaged(Damage::Skipped(next - offset), false)
+    });
+    offset = next;
+  }
+  repaired
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str, data: &str) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec())
+  }
+
+  fn testing_chunks() -> Vec<Chunk> {
+    vec![
+      chunk("IHDR", "header"),
+      chunk("tEXt", "Comment\0a text chunk"),
+      chunk("IDAT", "some image data"),
+      chunk("IEND", ""),
+    ]
+  }
+
+  fn testing_bytes() -> Vec<u8> {
+    testing_chunks().iter().flat_map(|chunk| chunk.as_bytes()).collect()
+  }
+
+  fn types(chunks: &[Chunk]) -> Vec<String> {
+    chunks.iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+  }
+
+  #[test]
+  fn test_undamaged() {
+    let repaired = repair(&testing_bytes(), false);
+    assert!(repaired.repairs.is_empty());
+    assert_eq!(types(&repaired.chunks), ["IHDR", "tEXt", "IDAT", "IEND"]);
+  }
+
+  #[test]
+  fn test_crc_mismatch() {
+    let mut bytes = testing_bytes();
+    let offset = 18;
+    bytes[offset + HEADER_LEN] ^= 1;
+    let expected = chunk("tEXt", "Bomment\0a text chunk").crc();
+    let stored = chunk("tEXt", "Comment\0a text chunk").crc();
+
+    let repaired = repair(&bytes, false);
+    assert_eq!(
+      repaired.repairs,
+      [Repair {
+        offset,
+        index: 1,
+        chunk_type: Some(ChunkType::from_str("tEXt").unwrap()),
+        damage: Damage::CrcMismatch(expected, stored),
+        fixed: false,
+      }]
+    );
+    assert_eq!(types(&repaired.chunks), ["IHDR", "IDAT", "IEND"]);
+
+    let repaired = repair(&bytes, true);
+    assert!(repaired.repairs[0].fixed);
+    assert_eq!(types(&repaired.chunks), ["IHDR", "tEXt", "IDAT", "IEND"]);
+    assert_eq!(repaired.chunks[1].data(), b"Bomment\0a text chunk");
+  }
+
+  #[test]
+  fn test_corrupt_length() {
+    for length in [3u32, 200, u32::MAX] {
+      let mut bytes = testing_bytes();
+      bytes[18..22].copy_from_slice(&length.to_be_bytes());
+      let repaired = repair(&bytes, false);
+      assert_eq!(repaired.repairs.len(), 1);
+      assert_eq!(repaired.repairs[0].damage, Damage::CorruptLength(length, 20));
+      assert_eq!(types(&repaired.chunks), ["IHDR", "tEXt", "IDAT", "IEND"]);
+      assert_eq!(repaired.chunks[1].data(), b"Comment\0a text chunk");
+    }
+  }
+
+  #[test]
+  fn test_resync() {
+    let mut bytes = testing_bytes();
+    bytes[18..26].copy_from_slice(&[0xff; 8]);
+    let repaired = repair(&bytes, true);
+    assert_eq!(repaired.repairs.len(), 1);
+    assert_eq!(repaired.repairs[0].damage, Damage::Skipped(32));
+    assert!(!repaired.repairs[0].fixed);
+    assert_eq!(types(&repaired.chunks), ["IHDR", "IDAT", "IEND"]);
+  }
+
+  #[test]
+  fn test_resync_budget() {
+    // fake headers every 12 bytes, each claiming most of the rest of the input
+    let mut bytes = chunk("IHDR", "header").as_bytes();
+    let garbage_len = 1 << 20;
+    while bytes.len() < garbage_len {
+      let length = (garbage_len - bytes.len()) as u32 / 2;
+      bytes.extend(length.to_be_bytes());
+      bytes.extend(b"ruSt\xff\xff\xff\xff");
+    }
+    bytes.extend(chunk("IEND", "").as_bytes());
+    let repaired = repair(&bytes, true);
+    assert_eq!(types(&repaired.chunks)[0], "IHDR");
+    assert!(matches!(repaired.repairs[0].damage, Damage::Skipped(_)));
+
+    let mut budget = 0;
+    assert_eq!(resync(&testing_bytes(), 0, &mut budget), testing_bytes().len());
+    let mut budget = 100;
+    assert_eq!(resync(&testing_bytes(), 0, &mut budget), 18);
+  }
+
+  #[test]
+  fn test_truncated() {
+    let bytes = testing_bytes();
+    let repaired = repair(&bytes[..bytes.len() - 3], true);
+    assert_eq!(types(&repaired.chunks), ["IHDR", "tEXt", "IDAT"]);
+    assert_eq!(repaired.repairs[0].damage, Damage::Skipped(9));
+  }
+
+  #[test]
+  fn test_display() {
+    let mut bytes = testing_bytes();
+    bytes[18 + HEADER_LEN] ^= 1;
+    let repaired = repair(&bytes, true);
+    assert!(repaired.repairs[0]
+      .to_string()
+      .starts_with("offset 18: chunk 1 (tEXt): crc mismatch, expected "));
+    assert!(repaired.repairs[0].to_string().ends_with(", fixed"));
+  }
+}
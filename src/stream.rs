@@ -0,0 +1,317 @@
+use std::io::{self, Read, Write};
+use std::result;
+
+use crate::chunk::{Chunk, ChunkError, ParseError, CRC, CRC_LEN, HEADER_LEN};
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngError};
+
+/// Size of the buffer used to stream chunk bodies
+const BUFFER_LEN: usize = 8 * 1024;
+
+/// Length and type of a chunk whose body has not been read yet
+#[derive(Debug)]
+pub struct ChunkHeader {
+  pub length: u32,
+  pub chunk_type: ChunkType,
+}
+
+impl ChunkHeader {
+  fn frame_len(&self) -> usize {
+    HEADER_LEN + self.length as usize + CRC_LEN
+  }
+}
+
+/// Reads the chunks of a png one at a time
+///
+/// As an iterator it yields every chunk, except IDAT chunks when `skip_image_data` is set,
+/// whose bodies are then skipped without being read into memory.
+pub struct PngReader<R: Read> {
+  reader: R,
+  skip_image_data: bool,
+  failed: bool,
+  offset: usize,
+  index: usize,
+}
+
+impl<R: Read> PngReader<R> {
+  /// Reads and checks the png signature
+  pub fn new(mut reader: R) -> result::Result<PngReader<R>, PngError> {
+    let mut header = [0u8; 8];
+    if read_full(&mut reader, &mut header)? != header.len() || header != Png::STANDARD_HEADER {
+      return Err(PngError::HeaderInValid);
+    }
+    Ok(PngReader {
+      reader,
+      skip_image_data: false,
+      failed: false,
+      offset: header.len(),
+      index: 0,
+    })
+  }
+
+  pub fn skip_image_data(mut self, skip_image_data: bool) -> PngReader<R> {
+    self.skip_image_data = skip_image_data;
+    self
+  }
+
+  /// Reads the length and type of the next chunk, `None` at the end of the input
+  ///
+  /// The body must then be consumed with `read_body`, `skip_body` or `PngWriter::copy_chunk`.
+  pub fn next_header(&mut self) -> result::Result<Option<ChunkHeader>, PngError> {
+    let mut header = [0u8; HEADER_LEN];
+    let read = read_full(&mut self.reader, &mut header)?;
+    if read == 0 {
+      return Ok(None);
+    }
+    if read < HEADER_LEN {
+      return Err(self.error(None, ChunkError::UnexpectedEof(HEADER_LEN, read)));
+    }
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let chunk_type = ChunkType::try_from([header[4], header[5], header[6], header[7]])
+      .map_err(|_| self.error(None, ChunkError::InvalidChunkType))?;
+    if length > Chunk::MAX_LENGTH {
+      return Err(self.error(Some(chunk_type), ChunkError::InvalidChunkLength(length)));
+    }
+    Ok(Some(ChunkHeader { length, chunk_type }))
+  }
+
+  /// Reads the body of the chunk and checks its crc
+  pub fn read_body(&mut self, header: ChunkHeader) -> result::Result<Chunk, PngError> {
+    let mut chunk_data = Vec::new();
+    (&mut self.reader)
+      .take(header.length as u64)
+      .read_to_end(&mut chunk_data)?;
+    let crc = self.read_crc(&header, chunk_data.len())?;
+    let chunk = Chunk::new(header.chunk_type, chunk_data);
+    if chunk.crc() != crc {
+      return Err(self.error(Some(header.chunk_type), ChunkError::InvalidCrc(chunk.crc(), crc)));
+    }
+    self.advance(&header);
+    Ok(chunk)
+  }
+
+  /// Skips the body of the chunk without checking its crc
+  pub fn skip_body(&mut self, header: &ChunkHeader) -> result::Result<(), PngError> {
+    let body_len = header.length as u64 + CRC_LEN as u64;
+    let skipped = io::copy(&mut (&mut self.reader).take(body_len), &mut io::sink())?;
+    if skipped != body_len {
+      let actual = HEADER_LEN + skipped as usize;
+      return Err(self.error(Some(header.chunk_type), ChunkError::UnexpectedEof(header.frame_len(), actual)));
+    }
+    self.advance(header);
+    Ok(())
+  }
+
+  /// Streams the body and crc of the chunk into `writer`, checking the crc on the way
+  fn copy_body<W: Write>(&mut self, header: &ChunkHeader, writer: &mut W) -> result::Result<(), PngError> {
+    let mut digest = CRC.digest();
+    digest.update(&header.chunk_type.bytes());
+    let mut buffer = [0u8; BUFFER_LEN];
+    let mut remaining = header.length as usize;
+    while remaining > 0 {
+      let len = remaining.min(BUFFER_LEN);
+      let read = read_full(&mut self.reader, &mut buffer[..len])?;
+      if read < len {
+        let actual = header.frame_len() - CRC_LEN - remaining + read;
+        return Err(self.error(Some(header.chunk_type), ChunkError::UnexpectedEof(header.frame_len(), actual)));
+      }
+      digest.update(&buffer[..len]);
+      writer.write_all(&buffer[..len])?;
+      remaining -= len;
+    }
+    let crc = self.read_crc(header, header.length as usize)?;
+    let calc_crc = digest.finalize();
+    if calc_crc != crc {
+      return Err(self.error(Some(header.chunk_type), ChunkError::InvalidCrc(calc_crc, crc)));
+    }
+    writer.write_all(&crc.to_be_bytes())?;
+    self.advance(header);
+    Ok(())
+  }
+
+  fn read_crc(&mut self, header: &ChunkHeader, data_len: usize) -> result::Result<u32, PngError> {
+    let mut crc = [0u8; CRC_LEN];
+    let read = if data_len == header.length as usize {
+      read_full(&mut self.reader, &mut crc)?
+    } else {
+      0
+    };
+    if read < CRC_LEN {
+      let actual = HEADER_LEN + data_len + read;
+      return Err(self.error(Some(header.chunk_type), ChunkError::UnexpectedEof(header.frame_len(), actual)));
+    }
+    Ok(u32::from_be_bytes(crc))
+  }
+
+  fn advance(&mut self, header: &ChunkHeader) {
+    self.offset += header.frame_len();
+    self.index += 1;
+  }
+
+  fn error(&self, chunk_type: Option<ChunkType>, error: ChunkError) -> PngError {
+    PngError::ChunksInvalid(ParseError {
+      offset: self.offset,
+      index: self.index,
+      chunk_type,
+      error,
+    })
+  }
+}
+
+impl<R: Read> Iterator for PngReader<R> {
+  type Item = result::Result<Chunk, PngError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed {
+      return None;
+    }
+    let item = loop {
+      let header = match self.next_header() {
+        Ok(Some(header)) => header,
+        Ok(None) => return None,
+        Err(error) => break Err(error),
+      };
+      if self.skip_image_data && header.chunk_type.bytes() == *b"IDAT" {
+        match self.skip_body(&header) {
+          Ok(()) => continue,
+          Err(error) => break Err(error),
+        }
+      }
+      break self.read_body(header);
+    };
+    self.failed = item.is_err();
+    Some(item)
+  }
+}
+
+/// Writes the png signature and then chunks one at a time
+pub struct PngWriter<W: Write> {
+  writer: W,
+}
+
+impl<W: Write> PngWriter<W> {
+  /// Writes the png signature
+  pub fn new(mut writer: W) -> io::Result<PngWriter<W>> {
+    writer.write_all(&Png::STANDARD_HEADER)?;
+    Ok(PngWriter { writer })
+  }
+
+  pub fn write_chunk(&mut self, chunk: &Chunk) -> io::Result<()> {
+    self.writer.write_all(&chunk.as_bytes())
+  }
+
+  /// Streams a chunk whose header was just read from `reader` without buffering its body
+  pub fn copy_chunk<R: Read>(
+    &mut self,
+    reader: &mut PngReader<R>,
+    header: &ChunkHeader,
+  ) -> result::Result<(), PngError> {
+    self.writer.write_all(&header.length.to_be_bytes())?;
+    self.writer.write_all(&header.chunk_type.bytes())?;
+    reader.copy_body(header, &mut self.writer)
+  }
+
+  /// Flushes and returns the underlying writer
+  pub fn finish(mut self) -> io::Result<W> {
+    self.writer.flush()?;
+    Ok(self.writer)
+  }
+}
+
+/// Reads until `buf` is full or the input ends, returning the number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+  let mut read = 0;
+  while read < buf.len() {
+    match reader.read(&mut buf[read..]) {
+      Ok(0) => break,
+      Ok(len) => read += len,
+      Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+      Err(error) => return Err(error),
+    }
+  }
+  Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::str::FromStr;
+
+  fn testing_png() -> Vec<u8> {
+    let chunks = [("IHDR", "header"), ("IDAT", "image data"), ("teXt", "hello"), ("IEND", "")]
+      .iter()
+      .map(|(chunk_type, data)| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec()))
+      .collect();
+    Png::from_chunks(chunks).as_bytes()
+  }
+
+  #[test]
+  fn test_read_chunks() {
+    let bytes = testing_png();
+    let reader = PngReader::new(bytes.as_slice()).unwrap();
+    let chunks: Vec<Chunk> = reader.collect::<result::Result<_, _>>().unwrap();
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[2].data_as_string().unwrap(), "hello");
+  }
+
+  #[test]
+  fn test_read_chunks_skip_image_data() {
+    let bytes = testing_png();
+    let reader = PngReader::new(bytes.as_slice()).unwrap().skip_image_data(true);
+    let chunk_types: Vec<String> = reader.map(|chunk| chunk.unwrap().chunk_type().to_string()).collect();
+    assert_eq!(chunk_types, ["IHDR", "teXt", "IEND"]);
+  }
+
+  #[test]
+  fn test_read_invalid_header() {
+    let mut bytes = testing_png();
+    bytes[0] = 0;
+    assert!(PngReader::new(bytes.as_slice()).is_err());
+    assert!(PngReader::new(&bytes[..4]).is_err());
+  }
+
+  #[test]
+  fn test_read_truncated() {
+    let bytes = testing_png();
+    for len in 8..bytes.len() {
+      let reader = PngReader::new(&bytes[..len]).unwrap();
+      let _ = reader.collect::<Vec<_>>();
+    }
+    let reader = PngReader::new(&bytes[..bytes.len() - 1]).unwrap();
+    match reader.last() {
+      Some(Err(PngError::ChunksInvalid(error))) => {
+        assert_eq!(error.index, 3);
+        assert_eq!(error.offset, bytes.len() - 12);
+      }
+      _ => panic!("expected a chunk parse error"),
+    }
+  }
+
+  #[test]
+  fn test_copy_chunks() {
+    let bytes = testing_png();
+    let mut reader = PngReader::new(bytes.as_slice()).unwrap();
+    let mut writer = PngWriter::new(Vec::new()).unwrap();
+    while let Some(header) = reader.next_header().unwrap() {
+      writer.copy_chunk(&mut reader, &header).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), bytes);
+  }
+
+  #[test]
+  fn test_copy_chunk_bad_crc() {
+    let mut bytes = testing_png();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    let mut reader = PngReader::new(bytes.as_slice()).unwrap();
+    let mut writer = PngWriter::new(Vec::new()).unwrap();
+    let mut result = Ok(());
+    while let Some(header) = reader.next_header().unwrap() {
+      result = writer.copy_chunk(&mut reader, &header);
+      if result.is_err() {
+        break;
+      }
+    }
+    assert!(matches!(result, Err(PngError::ChunksInvalid(_))));
+  }
+}
@@ -0,0 +1,190 @@
+//! Selecting the ancillary chunks removed by `strip`
+//!
+//! Chunks are selected by type or by category. Critical chunks are never selected, as the image
+//! cannot be rendered without them.
+
+use std::{error, fmt, result, str::FromStr};
+
+use crate::chunk_type::ChunkType;
+#[cfg(doc)]
+use crate::registry;
+
+/// Strip errors
+#[derive(Debug)]
+pub enum StripError {
+  /// The selector is neither a category nor a chunk type
+  InvalidSelector(String),
+}
+
+impl error::Error for StripError {}
+
+impl fmt::Display for StripError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StripError::InvalidSelector(selector) => write!(
+        f,
+        "Invalid selector {}, expected a chunk type or one of text, time, color, exif or private",
+        selector
+      ),
+    }
+  }
+}
+
+/// A group of related ancillary chunk types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+  /// tEXt, zTXt and iTXt
+  Text,
+  /// tIME
+  Time,
+  /// gAMA, cHRM, sRGB, iCCP, sBIT, cICP, mDCV and cLLI
+  ColorManagement,
+  /// eXIf
+  Exif,
+  /// Any chunk type with a lowercase second letter
+  Private,
+}
+
+impl Category {
+  /// Whether a chunk type belongs to the category
+  ///
+  /// The text, time and color management categories are the sections of the png spec defining
+  /// those chunks, as listed in the [`registry`].
+  pub fn contains(&self, chunk_type: &ChunkType) -> bool {
+    let section = match self {
+      Category::Text => "11.3.3.",
+      Category::Time => "11.3.5.",
+      Category::ColorManagement => "11.3.2.",
+      Category::Exif => return chunk_type.bytes() == *b"eXIf",
+      Category::Private => return !chunk_type.is_public(),
+    };
+    chunk_type
+      .definition()
+      .is_some_and(|definition| definition.section.starts_with(section))
+  }
+}
+
+impl FromStr for Category {
+  type Err = StripError;
+
+  fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+    match s {
+      "text" => Ok(Category::Text),
+      "time" => Ok(Category::Time),
+      "color" | "color-management" => Ok(Category::ColorManagement),
+      "exif" => Ok(Category::Exif),
+      "private" => Ok(Category::Private),
+      _ => Err(StripError::InvalidSelector(s.to_owned())),
+    }
+  }
+}
+
+/// A chunk type or a category
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+  Type(ChunkType),
+  Category(Category),
+}
+
+impl Selector {
+  pub fn matches(&self, chunk_type: &ChunkType) -> bool {
+    match self {
+      Selector::Type(selected) => selected == chunk_type,
+      Selector::Category(category) => category.contains(chunk_type),
+    }
+  }
+}
+
+impl FromStr for Selector {
+  type Err = StripError;
+
+  fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+    if let Ok(category) = Category::from_str(s) {
+      return Ok(Selector::Category(category));
+    }
+    ChunkType::from_str(s)
+      .map(Selector::Type)
+      .map_err(|_| StripError::InvalidSelector(s.to_owned()))
+  }
+}
+
+/// Which chunks to strip
+///
+/// Every ancillary chunk is stripped unless `remove` is set, in which case only the matching
+/// ones are. Chunks matching `keep` are always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StripFilter {
+  pub keep: Vec<Selector>,
+  pub remove: Vec<Selector>,
+}
+
+impl StripFilter {
+  /// Whether chunks of the given type are stripped
+  pub fn strips(&self, chunk_type: &ChunkType) -> bool {
+    let matches = |selectors: &[Selector]| selectors.iter().any(|selector| selector.matches(chunk_type));
+    !chunk_type.is_critical()
+      && (self.remove.is_empty() || matches(&self.remove))
+      && !matches(&self.keep)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chunk_type(name: &str) -> ChunkType {
+    ChunkType::from_str(name).unwrap()
+  }
+
+  fn selectors(names: &[&str]) -> Vec<Selector> {
+    names.iter().map(|name| Selector::from_str(name).unwrap()).collect()
+  }
+
+  #[test]
+  fn test_default_strips_ancillary() {
+    let filter = StripFilter::default();
+    for name in ["tEXt", "tIME", "gAMA", "eXIf", "ruSt", "pHYs"] {
+      assert!(filter.strips(&chunk_type(name)), "{}", name);
+    }
+    for name in ["IHDR", "PLTE", "IDAT", "IEND", "RuSt"] {
+      assert!(!filter.strips(&chunk_type(name)), "{}", name);
+    }
+  }
+
+  #[test]
+  fn test_keep() {
+    let filter = StripFilter {
+      keep: selectors(&["color", "pHYs"]),
+      ..StripFilter::default()
+    };
+    for name in ["gAMA", "cHRM", "sRGB", "iCCP", "sBIT", "cICP", "mDCV", "cLLI"] {
+      assert!(!filter.strips(&chunk_type(name)), "{}", name);
+    }
+    assert!(!filter.strips(&chunk_type("pHYs")));
+    assert!(filter.strips(&chunk_type("tEXt")));
+  }
+
+  #[test]
+  fn test_remove() {
+    let filter = StripFilter {
+      remove: selectors(&["text", "private", "IDAT"]),
+      keep: selectors(&["ruSt"]),
+    };
+    assert!(filter.strips(&chunk_type("zTXt")));
+    assert!(filter.strips(&chunk_type("abCd")));
+    assert!(!filter.strips(&chunk_type("ruSt")));
+    assert!(!filter.strips(&chunk_type("tIME")));
+    assert!(!filter.strips(&chunk_type("IDAT")));
+  }
+
+  #[test]
+  fn test_selector_from_str() {
+    assert_eq!(Selector::from_str("exif").unwrap(), Selector::Category(Category::Exif));
+    assert_eq!(
+      Selector::from_str("color-management").unwrap(),
+      Selector::Category(Category::ColorManagement)
+    );
+    assert_eq!(Selector::from_str("tIME").unwrap(), Selector::Type(chunk_type("tIME")));
+    assert!(matches!(Selector::from_str("colour"), Err(StripError::InvalidSelector(_))));
+  }
+}
@@ -0,0 +1,49 @@
+//! Temporary files for tests, named after the test process so parallel runs do not collide
+
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// A file or directory in the temporary directory, removed when dropped
+pub(crate) struct TestingFile(pub(crate) PathBuf);
+
+impl TestingFile {
+  /// A png with a single chunk of each given type and data
+  pub(crate) fn new(name: &str, chunks: &[(&str, &[u8])]) -> TestingFile {
+    let path = TestingFile::path(&format!("{}.png", name));
+    let chunks = chunks
+      .iter()
+      .map(|(chunk_type, data)| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()))
+      .collect();
+    fs::write(&path, Png::from_chunks(chunks).as_bytes()).unwrap();
+    TestingFile(path)
+  }
+
+  /// A directory of empty files
+  pub(crate) fn directory(name: &str, files: &[&str]) -> TestingFile {
+    let root = TestingFile::path(name);
+    for file in files {
+      let path = root.join(file);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, b"").unwrap();
+    }
+    TestingFile(root)
+  }
+
+  fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("pngme-{}-{}", std::process::id(), name))
+  }
+}
+
+impl Drop for TestingFile {
+  fn drop(&mut self) {
+    let _ = match self.0.is_dir() {
+      true => fs::remove_dir_all(&self.0),
+      false => fs::remove_file(&self.0),
+    };
+  }
+}
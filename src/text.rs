@@ -0,0 +1,428 @@
+//! Textual metadata stored in tEXt, zTXt and iTXt chunks
+//!
+//! All three start with a Latin-1 keyword of 1 to 79 printable characters and a null separator.
+//!
+//! | chunk  | rest of the data                                                               |
+//! |--------|--------------------------------------------------------------------------------|
+//! | `tEXt` | Latin-1 text                                                                   |
+//! | `zTXt` | compression method `0`, zlib compressed Latin-1 text                           |
+//! | `iTXt` | compression flag, compression method `0`, language tag, null, UTF-8 translated |
+//! |        | keyword, null, UTF-8 text, zlib compressed when the flag is `1`                |
+
+use std::io::{self, Read};
+use std::{error, fmt, result, str::FromStr};
+
+use flate2::read::ZlibDecoder;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image_data;
+
+/// Longest keyword allowed by the spec
+const MAX_KEYWORD_LEN: usize = 79;
+/// Longest decompressed text, far beyond any real metadata but bounding zlib bombs
+const MAX_TEXT_LEN: usize = 8 * 1024 * 1024;
+
+/// Text chunk errors
+#[derive(Debug)]
+pub enum TextChunkError {
+  /// The chunk is not a tEXt, zTXt or iTXt chunk
+  NotTextChunk(ChunkType),
+  /// A null separator is missing
+  MissingSeparator,
+  /// The keyword is empty, too long, or has non printable characters or extra spaces
+  InvalidKeyword(String),
+  InvalidCompressionFlag(u8),
+  InvalidCompressionMethod(u8),
+  /// The compressed text is not a valid zlib stream
+  Inflate(io::Error),
+  /// The decompressed text is longer than [`MAX_TEXT_LEN`]
+  TextTooLong,
+  /// The iTXt language tag is not made of hyphen separated words of 1 to 8 ASCII letters or digits
+  InvalidLanguageTag(String),
+  /// The iTXt text, language tag or translated keyword is not valid UTF-8
+  InvalidUTF8,
+  /// A tEXt or zTXt chunk cannot hold this character
+  NotLatin1(char),
+}
+
+impl error::Error for TextChunkError {}
+
+impl fmt::Display for TextChunkError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TextChunkError::NotTextChunk(chunk_type) => {
+        write!(f, "{} is not a tEXt, zTXt or iTXt chunk", chunk_type)
+      }
+      TextChunkError::MissingSeparator => write!(f, "A null separator is missing"),
+      TextChunkError::InvalidKeyword(keyword) => write!(
+        f,
+        "Invalid keyword {:?}, expected 1 to {} printable Latin-1 characters without extra spaces",
+        keyword, MAX_KEYWORD_LEN
+      ),
+      TextChunkError::InvalidCompressionFlag(flag) => write!(f, "Invalid compression flag {}", flag),
+      TextChunkError::InvalidCompressionMethod(method) => {
+        write!(f, "Invalid compression method {}", method)
+      }
+      TextChunkError::Inflate(error) => write!(f, "Invalid compressed text: {}", error),
+      TextChunkError::TextTooLong => write!(f, "The decompressed text is longer than {} bytes", MAX_TEXT_LEN),
+      TextChunkError::InvalidLanguageTag(tag) => write!(
+        f,
+        "Invalid language tag {:?}, expected words of 1 to 8 ASCII letters or digits separated by hyphens",
+        tag
+      ),
+      TextChunkError::InvalidUTF8 => write!(f, "International text is invalid UTF8"),
+      TextChunkError::NotLatin1(char) => write!(
+        f,
+        "{:?} is not a Latin-1 character, use an iTXt chunk instead",
+        char
+      ),
+    }
+  }
+}
+
+/// A decoded tEXt, zTXt or iTXt chunk
+///
+/// Chunks with a language tag or a translated keyword are iTXt chunks, compressed ones zTXt
+/// chunks, and the others tEXt chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+  pub keyword: String,
+  /// RFC 3066 language of the text, iTXt only
+  pub language_tag: Option<String>,
+  /// The keyword in the language of the text, iTXt only
+  pub translated_keyword: Option<String>,
+  pub text: String,
+  pub compressed: bool,
+}
+
+impl TextChunk {
+  /// A tEXt chunk, or zTXt when `compressed`
+  pub fn new(keyword: &str, text: &str, compressed: bool) -> TextChunk {
+    TextChunk {
+      keyword: keyword.to_owned(),
+      language_tag: None,
+      translated_keyword: None,
+      text: text.to_owned(),
+      compressed,
+    }
+  }
+
+  /// An iTXt chunk with an empty language tag and translated keyword
+  pub fn international(keyword: &str, text: &str, compressed: bool) -> TextChunk {
+    TextChunk {
+      language_tag: Some(String::new()),
+      translated_keyword: Some(String::new()),
+      ..TextChunk::new(keyword, text, compressed)
+    }
+  }
+
+  /// Whether a chunk type is one of the text chunk types
+  pub fn is_text_chunk(chunk_type: &ChunkType) -> bool {
+    matches!(&chunk_type.bytes(), b"tEXt" | b"zTXt" | b"iTXt")
+  }
+
+  fn is_international(&self) -> bool {
+    self.language_tag.is_some() || self.translated_keyword.is_some()
+  }
+
+  /// The chunk type this text is stored in
+  pub fn chunk_type(&self) -> ChunkType {
+    let name = match (self.is_international(), self.compressed) {
+      (true, _) => "iTXt",
+      (false, true) => "zTXt",
+      (false, false) => "tEXt",
+    };
+    ChunkType::from_str(name).unwrap()
+  }
+
+  /// Serializes the text as a chunk
+  pub fn to_chunk(&self) -> result::Result<Chunk, TextChunkError> {
+    let mut data = encode_latin1(&self.keyword)?;
+    validate_keyword(&data)?;
+    data.push(0);
+    if self.is_international() {
+      data.extend([self.compressed as u8, 0]);
+      let language_tag = self.language_tag.as_deref().unwrap_or_default();
+      validate_language_tag(language_tag)?;
+      data.extend(language_tag.bytes());
+      data.push(0);
+      data.extend(self.translated_keyword.as_deref().unwrap_or_default().bytes());
+      data.push(0);
+      match self.compressed {
+        true => data.extend(image_data::deflate(self.text.as_bytes(), 9)),
+        false => data.extend(self.text.bytes()),
+      }
+    } else if self.compressed {
+      data.push(0);
+      data.extend(image_data::deflate(&encode_latin1(&self.text)?, 9));
+    } else {
+      data.extend(encode_latin1(&self.text)?);
+    }
+    Ok(Chunk::new(self.chunk_type(), data))
+  }
+}
+
+fn encode_latin1(text: &str) -> result::Result<Vec<u8>, TextChunkError> {
+  text
+    .chars()
+    .map(|char| u8::try_from(char).map_err(|_| TextChunkError::NotLatin1(char)))
+    .collect()
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+  bytes.iter().map(|&byte| byte as char).collect()
+}
+
+fn validate_keyword(keyword: &[u8]) -> result::Result<(), TextChunkError> {
+  let printable = keyword
+    .iter()
+    .all(|&byte| (32..=126).contains(&byte) || byte >= 161);
+  let spaced = keyword.first() == Some(&b' ')
+    || keyword.last() == Some(&b' ')
+    || keyword.windows(2).any(|pair| pair == b"  ");
+  if keyword.is_empty() || keyword.len() > MAX_KEYWORD_LEN || !printable || spaced {
+    return Err(TextChunkError::InvalidKeyword(decode_latin1(keyword)));
+  }
+  Ok(())
+}
+
+/// Checks an RFC 3066 language tag, which may be empty
+fn validate_language_tag(tag: &str) -> result::Result<(), TextChunkError> {
+  let valid_word =
+    |word: &str| (1..=8).contains(&word.len()) && word.bytes().all(|byte| byte.is_ascii_alphanumeric());
+  match tag.is_empty() || tag.split('-').all(valid_word) {
+    true => Ok(()),
+    false => Err(TextChunkError::InvalidLanguageTag(tag.to_owned())),
+  }
+}
+
+/// Splits at the first null byte, dropping it
+fn split_null(bytes: &[u8]) -> result::Result<(&[u8], &[u8]), TextChunkError> {
+  let idx = bytes
+    .iter()
+    .position(|&byte| byte == 0)
+    .ok_or(TextChunkError::MissingSeparator)?;
+  Ok((&bytes[..idx], &bytes[idx + 1..]))
+}
+
+fn inflate(compressed: &[u8]) -> result::Result<Vec<u8>, TextChunkError> {
+  let mut inflated = Vec::new();
+  ZlibDecoder::new(compressed)
+    .take(MAX_TEXT_LEN as u64 + 1)
+    .read_to_end(&mut inflated)
+    .map_err(TextChunkError::Inflate)?;
+  match inflated.len() > MAX_TEXT_LEN {
+    true => Err(TextChunkError::TextTooLong),
+    false => Ok(inflated),
+  }
+}
+
+fn utf8(bytes: Vec<u8>) -> result::Result<String, TextChunkError> {
+  String::from_utf8(bytes).map_err(|_| TextChunkError::InvalidUTF8)
+}
+
+fn compression_method(method: Option<&u8>) -> result::Result<(), TextChunkError> {
+  match method {
+    Some(0) => Ok(()),
+    Some(&method) => Err(TextChunkError::InvalidCompressionMethod(method)),
+    None => Err(TextChunkError::MissingSeparator),
+  }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+  type Error = TextChunkError;
+
+  fn try_from(chunk: &Chunk) -> result::Result<Self, Self::Error> {
+    TextChunk::try_from((chunk.chunk_type(), chunk.data()))
+  }
+}
+
+impl TryFrom<(&ChunkType, &[u8])> for TextChunk {
+  type Error = TextChunkError;
+
+  fn try_from((chunk_type, data): (&ChunkType, &[u8])) -> result::Result<Self, Self::Error> {
+    let (keyword, rest) = split_null(data)?;
+    validate_keyword(keyword)?;
+    let keyword = decode_latin1(keyword);
+    match &chunk_type.bytes() {
+      b"tEXt" => Ok(TextChunk::new(&keyword, &decode_latin1(rest), false)),
+      b"zTXt" => {
+        compression_method(rest.first())?;
+        let text = decode_latin1(&inflate(&rest[1..])?);
+        Ok(TextChunk::new(&keyword, &text, true))
+      }
+      b"iTXt" => {
+        let compressed = match rest.first() {
+          Some(0) => false,
+          Some(1) => true,
+          Some(&flag) => return Err(TextChunkError::InvalidCompressionFlag(flag)),
+          None => return Err(TextChunkError::MissingSeparator),
+        };
+        compression_method(rest.get(1))?;
+        let (language_tag, rest) = split_null(&rest[2..])?;
+        let (translated_keyword, text) = split_null(rest)?;
+        let text = match compressed {
+          true => inflate(text)?,
+          false => text.to_vec(),
+        };
+        let language_tag = utf8(language_tag.to_vec())?;
+        validate_language_tag(&language_tag)?;
+        Ok(TextChunk {
+          keyword,
+          language_tag: Some(language_tag),
+          translated_keyword: Some(utf8(translated_keyword.to_vec())?),
+          text: utf8(text)?,
+          compressed,
+        })
+      }
+      _ => Err(TextChunkError::NotTextChunk(*chunk_type)),
+    }
+  }
+}
+
+impl fmt::Display for TextChunk {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.keyword)?;
+    let language_tag = self.language_tag.as_deref().unwrap_or_default();
+    let translated_keyword = self.translated_keyword.as_deref().unwrap_or_default();
+    match (language_tag.is_empty(), translated_keyword.is_empty()) {
+      (true, true) => {}
+      (false, true) => write!(f, " [{}]", language_tag)?,
+      (true, false) => write!(f, " ({})", translated_keyword)?,
+      (false, false) => write!(f, " [{}] ({})", language_tag, translated_keyword)?,
+    }
+    write!(f, ": {}", self.text)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+  }
+
+  #[test]
+  fn test_parse_text() {
+    let text = TextChunk::try_from(&chunk("tEXt", b"Comment\0caf\xe9")).unwrap();
+    assert_eq!(text, TextChunk::new("Comment", "café", false));
+    assert_eq!(text.to_string(), "Comment: café");
+  }
+
+  #[test]
+  fn test_parse_compressed_text() {
+    let mut data = b"Description\0\0".to_vec();
+    data.extend(image_data::deflate(b"a long description", 6));
+    let text = TextChunk::try_from(&chunk("zTXt", &data)).unwrap();
+    assert_eq!(text, TextChunk::new("Description", "a long description", true));
+  }
+
+  #[test]
+  fn test_parse_international_text() {
+    let text = TextChunk::try_from(&chunk("iTXt", "Title\0\0\0ja\0タイトル\0猫".as_bytes())).unwrap();
+    assert_eq!(text.keyword, "Title");
+    assert_eq!(text.language_tag.as_deref(), Some("ja"));
+    assert_eq!(text.translated_keyword.as_deref(), Some("タイトル"));
+    assert_eq!(text.text, "猫");
+    assert!(!text.compressed);
+    assert_eq!(text.to_string(), "Title [ja] (タイトル): 猫");
+  }
+
+  #[test]
+  fn test_round_trip() {
+    for text in [
+      TextChunk::new("Comment", "hello", false),
+      TextChunk::new("Comment", "hello", true),
+      TextChunk::international("Comment", "héllo 世界", false),
+      TextChunk::international("Comment", "héllo 世界", true),
+    ] {
+      let chunk = text.to_chunk().unwrap();
+      assert_eq!(chunk.chunk_type(), &text.chunk_type());
+      assert_eq!(TextChunk::try_from(&chunk).unwrap(), text);
+    }
+    assert_eq!(TextChunk::new("a", "b", false).chunk_type().to_string(), "tEXt");
+    assert_eq!(TextChunk::new("a", "b", true).chunk_type().to_string(), "zTXt");
+    assert_eq!(TextChunk::international("a", "b", true).chunk_type().to_string(), "iTXt");
+  }
+
+  #[test]
+  fn test_invalid_keyword() {
+    for keyword in ["", " Comment", "Comment ", "Two  spaces", "tab\there", &"k".repeat(80)] {
+      assert!(matches!(
+        TextChunk::new(keyword, "text", false).to_chunk(),
+        Err(TextChunkError::InvalidKeyword(_))
+      ));
+    }
+    assert!(TextChunk::new("Two spaces", "text", false).to_chunk().is_ok());
+    assert!(matches!(
+      TextChunk::try_from(&chunk("tEXt", b"no separator")),
+      Err(TextChunkError::MissingSeparator)
+    ));
+  }
+
+  #[test]
+  fn test_language_tag() {
+    for tag in ["", "ja", "en-US", "x-klingon", "zh-Hant-TW"] {
+      let text = TextChunk {
+        language_tag: Some(tag.to_owned()),
+        ..TextChunk::international("Title", "text", false)
+      };
+      assert_eq!(TextChunk::try_from(&text.to_chunk().unwrap()).unwrap(), text);
+    }
+    for tag in ["en_US", "-en", "en-", "toolongtag", "日本"] {
+      let text = TextChunk {
+        language_tag: Some(tag.to_owned()),
+        ..TextChunk::international("Title", "text", false)
+      };
+      assert!(matches!(text.to_chunk(), Err(TextChunkError::InvalidLanguageTag(_))), "{}", tag);
+    }
+    assert!(matches!(
+      TextChunk::try_from(&chunk("iTXt", b"Title\0\0\0en US\0\0text")),
+      Err(TextChunkError::InvalidLanguageTag(_))
+    ));
+  }
+
+  #[test]
+  fn test_text_too_long() {
+    let mut data = b"Comment\0\0".to_vec();
+    data.extend(image_data::deflate(&vec![b'a'; MAX_TEXT_LEN + 1], 9));
+    assert!(matches!(TextChunk::try_from(&chunk("zTXt", &data)), Err(TextChunkError::TextTooLong)));
+    let mut data = b"Comment\0\0".to_vec();
+    data.extend(image_data::deflate(&vec![b'a'; MAX_TEXT_LEN], 9));
+    assert_eq!(TextChunk::try_from(&chunk("zTXt", &data)).unwrap().text.len(), MAX_TEXT_LEN);
+  }
+
+  #[test]
+  fn test_not_latin1() {
+    assert!(matches!(
+      TextChunk::new("Comment", "世界", false).to_chunk(),
+      Err(TextChunkError::NotLatin1('世'))
+    ));
+  }
+
+  #[test]
+  fn test_invalid_chunks() {
+    assert!(matches!(
+      TextChunk::try_from(&chunk("ruSt", b"Comment\0text")),
+      Err(TextChunkError::NotTextChunk(_))
+    ));
+    assert!(matches!(
+      TextChunk::try_from(&chunk("zTXt", b"Comment\0\x01data")),
+      Err(TextChunkError::InvalidCompressionMethod(1))
+    ));
+    assert!(matches!(
+      TextChunk::try_from(&chunk("zTXt", b"Comment\0\0not zlib")),
+      Err(TextChunkError::Inflate(_))
+    ));
+    assert!(matches!(
+      TextChunk::try_from(&chunk("iTXt", b"Comment\0\x02\0\0\0text")),
+      Err(TextChunkError::InvalidCompressionFlag(2))
+    ));
+    assert!(matches!(
+      TextChunk::try_from(&chunk("iTXt", b"Comment\0\0\0\0\0\xff")),
+      Err(TextChunkError::InvalidUTF8)
+    ));
+  }
+}
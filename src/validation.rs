@@ -0,0 +1,365 @@
+use std::fmt;
+
+use crate::apng::{self, ApngError};
+use crate::chunk::{ChunkError, ChunkRef, ParseError};
+use crate::chunk_type::ChunkType;
+use crate::image_header::{ColorType, ImageHeader, ImageHeaderError};
+use crate::registry::{self, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error => write!(f, "error"),
+    }
+  }
+}
+
+/// The png rule broken by a violation
+#[derive(Debug)]
+pub enum Rule {
+  /// The chunk could not be parsed
+  Malformed(ChunkError),
+  /// A required chunk is absent
+  MissingChunk(&'static str),
+  ImageHeaderNotFirst,
+  InvalidImageHeader(ImageHeaderError),
+  /// The chunk may appear only once
+  Duplicate,
+  /// IDAT chunks are separated by other chunks
+  NonConsecutiveImageData,
+  /// The chunk appears after IEND
+  ChunkAfterEnd,
+  /// The chunk must appear before the given chunk
+  MustPrecede(&'static str),
+  /// The chunk must appear after the given chunk
+  MustFollow(&'static str),
+  /// PLTE is not allowed for grayscale images
+  UnexpectedPalette,
+  /// A critical chunk that decoders cannot know about
+  UnknownCritical,
+  /// A public chunk that is neither in the png spec nor a registered extension
+  UnknownPublic,
+  /// The reserved bit of the chunk type is set
+  ReservedBit,
+  /// The chunk should not appear together with the given chunk
+  Conflict(&'static str),
+  /// The animation chunks are inconsistent
+  Animation(ApngError),
+}
+
+impl Rule {
+  /// Stable snake case identifier of the rule
+  pub fn name(&self) -> &'static str {
+    match self {
+      Rule::Malformed(_) => "malformed",
+      Rule::MissingChunk(_) => "missing_chunk",
+      Rule::ImageHeaderNotFirst => "image_header_not_first",
+      Rule::InvalidImageHeader(_) => "invalid_image_header",
+      Rule::Duplicate => "duplicate",
+      Rule::NonConsecutiveImageData => "non_consecutive_image_data",
+      Rule::ChunkAfterEnd => "chunk_after_end",
+      Rule::MustPrecede(_) => "must_precede",
+      Rule::MustFollow(_) => "must_follow",
+      Rule::UnexpectedPalette => "unexpected_palette",
+      Rule::UnknownCritical => "unknown_critical",
+      Rule::UnknownPublic => "unknown_public",
+      Rule::ReservedBit => "reserved_bit",
+      Rule::Conflict(_) => "conflict",
+      Rule::Animation(_) => "animation",
+    }
+  }
+}
+
+impl fmt::Display for Rule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Rule::Malformed(error) => write!(f, "malformed chunk: {}", error),
+      Rule::MissingChunk(chunk_type) => write!(f, "missing {} chunk", chunk_type),
+      Rule::ImageHeaderNotFirst => write!(f, "IHDR must be the first chunk"),
+      Rule::InvalidImageHeader(error) => write!(f, "invalid IHDR: {}", error),
+      Rule::Duplicate => write!(f, "chunk must not appear more than once"),
+      Rule::NonConsecutiveImageData => write!(f, "IDAT chunks must be consecutive"),
+      Rule::ChunkAfterEnd => write!(f, "chunk appears after IEND"),
+      Rule::MustPrecede(chunk_type) => write!(f, "chunk must appear before {}", chunk_type),
+      Rule::MustFollow(chunk_type) => write!(f, "chunk must appear after {}", chunk_type),
+      Rule::UnexpectedPalette => write!(f, "PLTE must not appear in grayscale images"),
+      Rule::UnknownCritical => write!(f, "unknown critical chunk"),
+      Rule::UnknownPublic => write!(f, "unknown public chunk"),
+      Rule::ReservedBit => write!(f, "the third letter of the chunk type must be uppercase"),
+      Rule::Conflict(chunk_type) => write!(f, "chunk should not appear together with {}", chunk_type),
+      Rule::Animation(error) => write!(f, "invalid animation: {}", error),
+    }
+  }
+}
+
+/// A broken png rule and where it was found
+#[derive(Debug)]
+pub struct Violation {
+  /// Index of the offending chunk, `None` for rules about the whole png
+  pub index: Option<usize>,
+  pub chunk_type: Option<ChunkType>,
+  pub severity: Severity,
+  pub rule: Rule,
+}
+
+impl Violation {
+  fn error(index: usize, chunk: &ChunkRef<'_>, rule: Rule) -> Violation {
+    Violation {
+      index: Some(index),
+      chunk_type: Some(*chunk.chunk_type()),
+      severity: Severity::Error,
+      rule,
+    }
+  }
+
+  fn warning(index: usize, chunk: &ChunkRef<'_>, rule: Rule) -> Violation {
+    Violation {
+      severity: Severity::Warning,
+      ..Violation::error(index, chunk, rule)
+    }
+  }
+
+  fn missing(chunk_type: &'static str) -> Violation {
+    Violation {
+      index: None,
+      chunk_type: None,
+      severity: Severity::Error,
+      rule: Rule::MissingChunk(chunk_type),
+    }
+  }
+}
+
+impl From<ParseError> for Violation {
+  fn from(error: ParseError) -> Violation {
+    Violation {
+      index: Some(error.index),
+      chunk_type: error.chunk_type,
+      severity: Severity::Error,
+      rule: Rule::Malformed(error.error),
+    }
+  }
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match (self.index, self.chunk_type) {
+      (Some(index), Some(chunk_type)) => write!(f, "chunk {} ({})", index, chunk_type)?,
+      (Some(index), None) => write!(f, "chunk {}", index)?,
+      _ => write!(f, "png")?,
+    }
+    write!(f, ": {}: {}", self.severity, self.rule)
+  }
+}
+
+/// Checks the chunks against their [`registry`] definitions, the IHDR rules of the png spec and the
+/// animation chunks of an APNG
+///
+/// Only the types of the chunks are read, besides the data of IHDR and the animation chunks.
+pub fn validate(chunks: &[ChunkRef<'_>]) -> Vec<Violation> {
+  let mut violations = Vec::new();
+  let is = |chunk: &ChunkRef<'_>, name: &[u8; 4]| chunk.chunk_type().bytes() == *name;
+  let position = |name: &[u8; 4]| chunks.iter().position(|chunk| is(chunk, name));
+  let palette = position(b"PLTE");
+  let image_data = position(b"IDAT");
+  let end = position(b"IEND");
+
+  let header = match position(b"IHDR") {
+    Some(index) => {
+      if index != 0 {
+        violations.push(Violation::error(index, &chunks[index], Rule::ImageHeaderNotFirst));
+      }
+      ImageHeader::try_from((chunks[index].chunk_type(), chunks[index].data()))
+        .map_err(|error| {
+          violations.push(Violation::error(index, &chunks[index], Rule::InvalidImageHeader(error)))
+        })
+        .ok()
+    }
+    None => None,
+  };
+  for definition in registry::DEFINITIONS.iter().filter(|definition| definition.multiplicity.is_required()) {
+    if !chunks.iter().any(|chunk| chunk.chunk_type().definition() == Some(definition)) {
+      violations.push(Violation::missing(definition.chunk_type));
+    }
+  }
+
+  let mut seen: Vec<[u8; 4]> = Vec::new();
+  for (index, chunk) in chunks.iter().enumerate() {
+    let chunk_type = chunk.chunk_type();
+    let name = chunk_type.bytes();
+    if !chunk_type.is_reserved_bit_valid() {
+      violations.push(Violation::error(index, chunk, Rule::ReservedBit));
+    }
+    let definition = chunk_type.definition();
+    if definition.is_some_and(|definition| definition.multiplicity.is_unique()) && seen.contains(&name) {
+      violations.push(Violation::error(index, chunk, Rule::Duplicate));
+    }
+    seen.push(name);
+    if end.is_some_and(|end| index > end) {
+      violations.push(Violation::error(index, chunk, Rule::ChunkAfterEnd));
+    }
+    let Some(definition) = definition else {
+      if chunk_type.is_critical() {
+        violations.push(Violation::error(index, chunk, Rule::UnknownCritical));
+      } else if chunk_type.is_public() {
+        violations.push(Violation::warning(index, chunk, Rule::UnknownPublic));
+      }
+      continue;
+    };
+    let mut error = |rule| violations.push(Violation::error(index, chunk, rule));
+    match definition.ordering {
+      Ordering::Consecutive if image_data != Some(index) && !is(&chunks[index - 1], b"IDAT") => {
+        error(Rule::NonConsecutiveImageData)
+      }
+      Ordering::BeforePalette if palette.is_some_and(|palette| index > palette) => error(Rule::MustPrecede("PLTE")),
+      Ordering::AfterPalette if palette.is_some_and(|palette| index < palette) => error(Rule::MustFollow("PLTE")),
+      _ => {}
+    }
+    let before_image_data = matches!(
+      definition.ordering,
+      Ordering::BeforePalette | Ordering::AfterPalette | Ordering::BeforeImageData
+    );
+    if before_image_data && image_data.is_some_and(|image_data| index > image_data) {
+      error(Rule::MustPrecede("IDAT"));
+    }
+  }
+
+  match (header.map(|header| header.color_type), palette) {
+    (Some(ColorType::Indexed), None) => violations.push(Violation::missing("PLTE")),
+    (Some(ColorType::Grayscale | ColorType::GrayscaleAlpha), Some(index)) => {
+      violations.push(Violation::error(index, &chunks[index], Rule::UnexpectedPalette))
+    }
+    _ => {}
+  }
+  if let (Some(_), Some(index)) = (position(b"iCCP"), position(b"sRGB")) {
+    violations.push(Violation::warning(index, &chunks[index], Rule::Conflict("iCCP")));
+  }
+  if let Some(header) = header {
+    for (index, error) in apng::validate(chunks, header.width, header.height) {
+      violations.push(match index {
+        Some(index) => Violation::error(index, &chunks[index], Rule::Animation(error)),
+        None => Violation {
+          index: None,
+          chunk_type: None,
+          severity: Severity::Error,
+          rule: Rule::Animation(error),
+        },
+      });
+    }
+  }
+  violations
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chunk::Chunk;
+  use std::str::FromStr;
+
+  fn chunk(chunk_type: &str) -> Chunk {
+    let chunk_data = match chunk_type {
+      "IHDR" => vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0],
+      _ => vec![],
+    };
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), chunk_data)
+  }
+
+  fn violations(chunk_types: &[&str]) -> Vec<Violation> {
+    let chunks: Vec<Chunk> = chunk_types.iter().map(|chunk_type| chunk(chunk_type)).collect();
+    validate(&chunks.iter().map(ChunkRef::from).collect::<Vec<_>>())
+  }
+
+  fn rules(chunk_types: &[&str]) -> Vec<String> {
+    violations(chunk_types)
+      .iter()
+      .map(|violation| violation.to_string())
+      .collect()
+  }
+
+  #[test]
+  fn test_valid_png() {
+    assert!(violations(&["IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "teXt", "IEND"]).is_empty());
+  }
+
+  #[test]
+  fn test_missing_chunks() {
+    assert_eq!(
+      rules(&["PLTE"]),
+      ["png: error: missing IHDR chunk", "png: error: missing IDAT chunk", "png: error: missing IEND chunk"]
+    );
+  }
+
+  #[test]
+  fn test_image_header_not_first() {
+    assert_eq!(
+      rules(&["gAMA", "IHDR", "IDAT", "IEND"]),
+      ["chunk 1 (IHDR): error: IHDR must be the first chunk"]
+    );
+  }
+
+  #[test]
+  fn test_ordering() {
+    assert_eq!(
+      rules(&["IHDR", "IDAT", "teXt", "IDAT", "PLTE", "gAMA", "IEND", "teXt"]),
+      [
+        "chunk 3 (IDAT): error: IDAT chunks must be consecutive",
+        "chunk 4 (PLTE): error: chunk must appear before IDAT",
+        "chunk 5 (gAMA): error: chunk must appear before PLTE",
+        "chunk 5 (gAMA): error: chunk must appear before IDAT",
+        "chunk 7 (teXt): error: chunk appears after IEND",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_duplicates() {
+    assert_eq!(
+      rules(&["IHDR", "PLTE", "PLTE", "IDAT", "IEND", "IEND"]),
+      [
+        "chunk 2 (PLTE): error: chunk must not appear more than once",
+        "chunk 5 (IEND): error: chunk must not appear more than once",
+        "chunk 5 (IEND): error: chunk appears after IEND",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_unknown_critical_and_conflict() {
+    let violations = violations(&["IHDR", "iCCP", "sRGB", "RuSt", "IDAT", "IEND"]);
+    assert_eq!(violations.len(), 2);
+    assert!(matches!(violations[0].rule, Rule::UnknownCritical));
+    assert_eq!(violations[1].severity, Severity::Warning);
+  }
+
+  #[test]
+  fn test_unknown_public_and_reserved_bit() {
+    assert_eq!(
+      rules(&["IHDR", "gIFg", "ruSt", "qUUX", "ruat", "IDAT", "IEND"]),
+      [
+        "chunk 3 (qUUX): warning: unknown public chunk",
+        "chunk 4 (ruat): error: the third letter of the chunk type must be uppercase",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_animation() {
+    let mut chunks: Vec<Chunk> = ["IHDR", "IDAT", "IEND"].iter().map(|chunk_type| chunk(chunk_type)).collect();
+    chunks.insert(2, apng::AnimationControl { num_frames: 1, num_plays: 0 }.to_chunk());
+    let chunks: Vec<ChunkRef<'_>> = chunks.iter().map(ChunkRef::from).collect();
+    let rules: Vec<String> = validate(&chunks).iter().map(|violation| violation.to_string()).collect();
+    assert_eq!(
+      rules,
+      [
+        "chunk 2 (acTL): error: chunk must appear before IDAT",
+        "png: error: invalid animation: acTL declares 1 frames but there are 0",
+      ]
+    );
+  }
+}